@@ -3,6 +3,7 @@ package com.alasdair_cooper.watch_history
 import android.content.Context
 import android.content.Intent
 import android.net.Uri
+import android.util.Log
 import androidx.browser.auth.AuthTabIntent
 import androidx.browser.customtabs.CustomTabsIntent
 import androidx.compose.runtime.getValue
@@ -12,6 +13,8 @@ import androidx.datastore.core.DataStore
 import androidx.datastore.preferences.core.Preferences
 import androidx.datastore.preferences.core.byteArrayPreferencesKey
 import androidx.datastore.preferences.core.edit
+import androidx.lifecycle.viewModelScope
+import com.alasdair_cooper.watch_history.shared.CoreException
 import com.alasdair_cooper.watch_history.shared.handleResponse
 import com.alasdair_cooper.watch_history.shared.processEvent
 import com.alasdair_cooper.watch_history.shared.view
@@ -26,10 +29,12 @@ import io.ktor.client.engine.cio.*
 import io.ktor.client.request.*
 import io.ktor.http.*
 import io.ktor.util.*
+import kotlinx.coroutines.delay
 import kotlinx.coroutines.flow.MutableSharedFlow
 import kotlinx.coroutines.flow.SharedFlow
 import kotlinx.coroutines.flow.first
 import kotlinx.coroutines.flow.map
+import kotlinx.coroutines.launch
 import javax.inject.Inject
 import javax.inject.Singleton
 import androidx.core.net.toUri
@@ -45,7 +50,12 @@ open class Core @Inject constructor(val keyValueStore: KeyValueStore) : androidx
     private val httpClient = HttpClient(CIO)
 
     suspend fun update(event: Event) {
-        val effects = processEvent(event.bincodeSerialize())
+        val effects = try {
+            processEvent(event.bincodeSerialize())
+        } catch (e: CoreException) {
+            Log.e(TAG, "Core failed to process event", e)
+            return
+        }
 
         val requests = Requests.bincodeDeserialize(effects)
         for (request in requests) {
@@ -60,44 +70,60 @@ open class Core @Inject constructor(val keyValueStore: KeyValueStore) : androidx
     private suspend fun processEffect(request: Request) {
         when (val effect = request.effect) {
             is Effect.Render -> {
-                val vm = ViewModel.bincodeDeserialize(view())
-                this.view = vm
+                renderView()
             }
 
             is Effect.Redirect -> {
-                this.view = ViewModel.bincodeDeserialize(view())
+                renderView()
                 _shellEvents.emit(ShellEvent.OpenUrl(effect.value.url.toUri()))
             }
 
+            is Effect.Delay -> {
+                // Waits in its own coroutine so effects requested alongside it aren't held up.
+                viewModelScope.launch {
+                    delay(effect.value.millis)
+
+                    resolve(request, byteArrayOf())
+                }
+            }
+
             is Effect.Http -> {
                 val response = requestHttp(httpClient, effect.value)
 
-                val effects = handleResponse(
-                    request.id.toUInt(), HttpResult.Ok(response).bincodeSerialize()
-                )
-
-                val requests = Requests.bincodeDeserialize(effects)
-                for (request in requests) {
-                    processEffect(request)
-                }
+                resolve(request, HttpResult.Ok(response).bincodeSerialize())
             }
 
             is Effect.KeyValue -> {
                 val response =
                     handleKeyValueOperation(effect.value) ?: throw Exception("Unsupported KeyValue operation: $effect")
 
-                val effects = handleResponse(
-                    request.id.toUInt(), KeyValueResult.Ok(response).bincodeSerialize()
-                )
-
-                val requests = Requests.bincodeDeserialize(effects)
-                for (request in requests) {
-                    processEffect(request)
-                }
+                resolve(request, KeyValueResult.Ok(response).bincodeSerialize())
             }
         }
     }
 
+    private fun renderView() {
+        try {
+            this.view = ViewModel.bincodeDeserialize(view())
+        } catch (e: CoreException) {
+            Log.e(TAG, "Core failed to render the view", e)
+        }
+    }
+
+    private suspend fun resolve(request: Request, response: ByteArray) {
+        val effects = try {
+            handleResponse(request.id.toUInt(), response)
+        } catch (e: CoreException) {
+            Log.e(TAG, "Core failed to handle response", e)
+            return
+        }
+
+        val requests = Requests.bincodeDeserialize(effects)
+        for (request in requests) {
+            processEffect(request)
+        }
+    }
+
     private suspend fun handleKeyValueOperation(
         operation: KeyValueOperation,
     ): KeyValueResponse? {
@@ -156,6 +182,10 @@ open class Core @Inject constructor(val keyValueStore: KeyValueStore) : androidx
         return HttpResponse(response.status.value.toShort(), headers, bytes)
     }
 
+    companion object {
+        private const val TAG = "Core"
+    }
+
     sealed class ShellEvent {
         data class OpenUrl(val url: Uri) : ShellEvent()
         data class CallbackReceived(val url: Uri) : ShellEvent()
@@ -214,11 +214,11 @@ fun Content(innerPadding: PaddingValues, core: Core = viewModel()) {
                 horizontalArrangement = Arrangement.SpaceBetween
             ) {
                 Text(
-                    text = film.title,
+                    text = film.film.title,
                     fontSize = 20.sp
                 )
                 Text(
-                    text = film.rating::class.simpleName ?: "",
+                    text = film.film.rating.label,
                     fontSize = 16.sp
                 )
             }
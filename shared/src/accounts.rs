@@ -0,0 +1,70 @@
+use crate::store::StoredValue;
+use crate::{Effect, Event};
+use crux_core::command::RequestBuilder;
+use crux_http::http::convert::{Deserialize, Serialize};
+use std::future::Future;
+
+const GITHUB_ACCOUNTS_STORAGE_KEY: &str = "github_accounts";
+
+fn stored_accounts() -> StoredValue<Accounts> {
+    StoredValue::new(GITHUB_ACCOUNTS_STORAGE_KEY)
+}
+
+/// The GitHub accounts that have been signed in to, identified by login.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Accounts {
+    pub logins: Vec<String>,
+    /// The account whose tokens are used for GitHub requests.
+    pub active: Option<String>,
+}
+
+impl Accounts {
+    /// Adds the account if it is not already signed in, and makes it the active account.
+    pub fn add(&mut self, login: impl Into<String>) {
+        let login = login.into();
+
+        if !self.logins.contains(&login) {
+            self.logins.push(login.clone());
+        }
+
+        self.active = Some(login);
+    }
+
+    /// Removes the account, falling back to the first remaining account if it was active.
+    pub fn remove(&mut self, login: &str) {
+        self.logins.retain(|x| x != login);
+
+        if self.active.as_deref() == Some(login) {
+            self.active = self.logins.first().cloned();
+        }
+    }
+
+    pub fn switch(&mut self, login: &str) -> bool {
+        if self.logins.iter().any(|x| x == login) {
+            self.active = Some(login.to_string());
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn is_active(&self, login: &str) -> bool {
+        self.active.as_deref() == Some(login)
+    }
+}
+
+#[derive(Clone)]
+pub struct AccountStore;
+
+impl AccountStore {
+    pub fn get_accounts(&self) -> RequestBuilder<Effect, Event, impl Future<Output = Accounts>> {
+        stored_accounts().get().map(Option::unwrap_or_default)
+    }
+
+    pub fn set_accounts(
+        &self,
+        accounts: Accounts,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_accounts().set(accounts)
+    }
+}
@@ -1,33 +1,222 @@
-use crate::film::WatchedFilm;
-use crate::github::{GitHubApiError, GitHubAuthenticatedUserResponse, GITHUB_OAUTH_AUTHORIZE_URL};
-use crate::markdown::parse_films_from_markdown;
+use crate::accounts::Accounts;
+use crate::cache::{CachedWatchHistory, Freshness};
+use crate::clock::Clock;
+use crate::delay::{delay, DelayOperation};
+use crate::error::AppError;
+use crate::etag::ETaggedResponse;
+use crate::film::{
+    viewings_of, FilmChange, FilmFilter, FilmGroup, FilmGrouping, FilmSummary, MonthOfYear, Rating,
+    WatchedFilm, WatchedFilmView,
+};
+use crate::github::{
+    GitHubApiError, GitHubAuthenticatedUserResponse, GitHubConditionalResponse,
+    GitHubDeviceCodeResponse, GitHubDeviceTokenPoll, GitHubFile, PutFileContents,
+    GITHUB_OAUTH_AUTHORIZE_URL,
+};
+use crate::login::{DeviceLogin, LoginError, LoginMode, PendingLogin};
+use crate::markdown::{
+    apply_change_to_markdown, check_tags, merge_markdown, parse_films_from_markdown, MergeConflict,
+    MergeSide, ParseReport,
+};
+use crate::queue::{QueuedChange, QueuedChangeStatus};
+use crate::rate_limit::RateLimit;
 use crate::redirect::{redirect, RedirectOperation};
 use crate::services::Services;
-use crate::tokens::Tokens;
+use crate::settings::WatchHistorySettings;
+use crate::tokens::{Secret, Tokens};
+use chrono::{DateTime, Utc};
 use crux_core::{
     macros::effect,
     render::{render, RenderOperation},
     Command,
 };
+use crux_http::http::StatusCode;
 use crux_http::protocol::HttpRequest;
+use crux_http::HttpError;
 use crux_kv::KeyValueOperation;
+use jiff::civil::Date;
 use rand::distr::{Alphanumeric, SampleString};
 use rand::rngs::StdRng;
 use rand::SeedableRng;
 use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
 use url::Url;
 
+const MAX_SAVE_RETRIES: u8 = 3;
+/// Rate limits that reset sooner than this are waited out rather than reported as an error.
+const MAX_AUTOMATIC_BACKOFF: Duration = Duration::from_secs(120);
+
 #[derive(Default)]
 pub struct Model {
     services: Services,
+    accounts: Accounts,
     user_info: Option<UserInfo>,
     films: Vec<WatchedFilm>,
+    /// The title drilled into, to show all of its viewings.
+    selected_title: Option<String>,
+    film_filter: Option<FilmFilter>,
+    film_grouping: Option<FilmGrouping>,
+    parse_report: ParseReport,
+    /// The last watch history file fetched from GitHub or the cache.
+    watch_history: Option<String>,
+    /// The `ETag` GitHub sent with the watch history, or `None` once it has been changed locally.
+    watch_history_etag: Option<String>,
+    freshness: Option<Freshness>,
+    save_status: Option<SaveStatus>,
+    change_queue: Vec<QueuedChange>,
+    /// Whether the stored queue has been read, as until then storing the queue would replace it.
+    change_queue_loaded: bool,
+    /// The id of the queued change currently being committed, so only one is replayed at a time.
+    change_in_flight: Option<String>,
+    /// The request budget GitHub reported with the most recent response.
+    rate_limit: Option<RateLimit>,
+    sync_paused_until: Option<DateTime<Utc>>,
+    settings: WatchHistorySettings,
+    settings_status: Option<SaveStatus>,
+    error: Option<AppError>,
+    retry_event: Option<Event>,
+    login_error: Option<LoginError>,
+    login_mode: LoginMode,
+    device_code: Option<Secret>,
+    device_login: Option<DeviceLogin>,
+}
+
+impl Model {
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            services: Services::with_clock(clock),
+            ..Default::default()
+        }
+    }
+
+    fn clear_account_state(&mut self) {
+        self.user_info = None;
+        self.settings = WatchHistorySettings::default();
+        self.settings_status = None;
+        self.films = Vec::new();
+        self.selected_title = None;
+        self.film_filter = None;
+        self.film_grouping = None;
+        self.parse_report = ParseReport::default();
+        self.watch_history = None;
+        self.watch_history_etag = None;
+        self.freshness = None;
+        self.save_status = None;
+        self.change_queue = Vec::new();
+        self.change_queue_loaded = false;
+        self.change_in_flight = None;
+        self.rate_limit = None;
+        self.error = None;
+        self.retry_event = None;
+    }
+
+    /// Loads what is kept locally for an account, so it can be shown before GitHub responds. The
+    /// settings are in place once this finishes, so requests for the account can follow it.
+    fn load_account_from_store(&self, login: String) -> Command<Effect, Event> {
+        self.services
+            .settings_store
+            .get_settings(&login)
+            .then_send(move |settings| Event::GotSettingsFromStore { login, settings })
+    }
+
+    /// The watch history as last fetched, for GitHub to answer with `304 Not Modified` if it is
+    /// unchanged.
+    fn cached_watch_history(&self) -> Option<ETaggedResponse> {
+        Some(ETaggedResponse {
+            etag: self.watch_history_etag.clone()?,
+            body: self.watch_history.clone()?,
+        })
+    }
+
+    /// The watch history as it looks locally, with the pending queued changes applied.
+    fn local_watch_history(&self) -> Option<String> {
+        let watch_history = self.watch_history.clone()?;
+
+        Some(
+            self.change_queue
+                .iter()
+                .filter(|queued| queued.status == QueuedChangeStatus::Pending)
+                .fold(watch_history, |markdown, queued| {
+                    apply_change_to_markdown(
+                        markdown.clone(),
+                        &queued.change,
+                        &self.settings.rating_scale,
+                    )
+                    .unwrap_or(markdown)
+                }),
+        )
+    }
+
+    /// Stores the queue once the stored one has been read, which the changes made before then are
+    /// added to.
+    fn store_change_queue(&self) -> Command<Effect, Event> {
+        match self.accounts.active.as_deref() {
+            Some(login) if self.change_queue_loaded => self
+                .services
+                .change_queue_store
+                .set_queue(login, self.change_queue.clone())
+                .build(),
+            None => Command::done(),
+        }
+    }
+
+    fn cache_watch_history(&self, login: &str) -> Command<Effect, Event> {
+        let Some(content) = self.watch_history.clone() else {
+            return Command::done();
+        };
+
+        self.services
+            .watch_history_cache_store
+            .set_cache(
+                login,
+                CachedWatchHistory {
+                    settings: self.settings.clone(),
+                    content,
+                    etag: self.watch_history_etag.clone(),
+                    fetched_at: self.services.clock.now(),
+                    films: self.films.clone(),
+                    parse_report: self.parse_report.clone(),
+                },
+            )
+            .build()
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct ViewModel {
-    pub films: Vec<WatchedFilm>,
+    /// The films matching `film_filter`, with the pending queued changes applied.
+    pub films: Vec<WatchedFilmView>,
+    pub film_summaries: Vec<FilmSummary>,
+    pub selected_film: Option<FilmSummary>,
+    /// Every viewing of the selected film, oldest first.
+    pub selected_film_viewings: Vec<WatchedFilm>,
+    pub film_filter: Option<FilmFilter>,
+    pub film_grouping: Option<FilmGrouping>,
+    /// The films grouped by `film_grouping`, empty when they are not grouped.
+    pub film_groups: Vec<FilmGroup>,
+    pub accounts: Vec<String>,
     pub user_info: Option<UserInfo>,
+    pub parse_report: ParseReport,
+    pub freshness: Option<Freshness>,
+    pub save_status: Option<SaveStatus>,
+    pub queued_changes: Vec<QueuedChange>,
+    pub rate_limit: Option<RateLimit>,
+    /// Set while waiting for a rate limit to reset before syncing again.
+    pub sync_paused_until: Option<DateTime<Utc>>,
+    pub settings: WatchHistorySettings,
+    pub settings_status: Option<SaveStatus>,
+    pub error: Option<AppError>,
+    pub login_error: Option<LoginError>,
+    pub login_mode: LoginMode,
+    pub device_login: Option<DeviceLogin>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum SaveStatus {
+    Pending,
+    Saved,
+    Failed(AppError),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
@@ -42,24 +231,128 @@ pub enum Event {
     InitialLoad,
     LoginButtonClicked,
     LogoutButtonClicked,
+    AddAccountButtonClicked,
+    SwitchAccount(String),
+    RemoveAccount(String),
     CallbackReceived(String),
+    AddFilm {
+        title: String,
+        rating: Rating,
+        year_watched: Option<i16>,
+        month_of_year_watched: Option<MonthOfYear>,
+        day_of_month_watched: Option<i8>,
+        rewatch: bool,
+        tags: Vec<String>,
+        companions: Vec<String>,
+        notes: Option<String>,
+    },
+    EditFilm {
+        film: WatchedFilm,
+        title: String,
+        rating: Rating,
+        tags: Vec<String>,
+        companions: Vec<String>,
+        notes: Option<String>,
+    },
+    DeleteFilm(WatchedFilm),
+    SelectFilmTitle(String),
+    ClearSelectedFilmTitle,
+    SetFilmFilter(Option<FilmFilter>),
+    SetFilmGrouping(Option<FilmGrouping>),
+    UpdateSettings(WatchHistorySettings),
+    ResetSettings,
+    DismissError,
+    RetryAfterError,
+    RetryQueuedChanges,
+    DiscardQueuedChange(String),
+    ResolveQueuedChangeConflict {
+        id: String,
+        keep: MergeSide,
+    },
+    ConnectivityRestored,
+    SetLoginMode(LoginMode),
+    LoginWithToken(Secret),
+    CancelDeviceLogin,
 
     // Local core events
     #[serde(skip)]
     RedirectToLogin,
     #[serde(skip)]
-    SetTokensInStore(Tokens),
+    StartDeviceLogin,
+    #[serde(skip)]
+    GotDeviceCode(GitHubDeviceCodeResponse),
+    #[serde(skip)]
+    PollDeviceLogin {
+        device_code: Secret,
+        interval: u64,
+    },
+    #[serde(skip)]
+    GotDeviceLoginPoll {
+        device_code: Secret,
+        interval: u64,
+        poll: GitHubDeviceTokenPoll,
+    },
+    #[serde(skip)]
+    GotPendingLogin {
+        code: Option<Secret>,
+        state: Option<String>,
+        pending_login: Option<PendingLogin>,
+    },
+    #[serde(skip)]
+    LoginFailed(LoginError),
+    #[serde(skip)]
+    ErrorOccurred {
+        error: AppError,
+        retry: Box<Event>,
+    },
+    #[serde(skip)]
+    GetAccountsFromStore,
+    #[serde(skip)]
+    GotAccountsFromStore(Accounts),
+    #[serde(skip)]
+    GotLegacyTokensFromStore(Option<Tokens>),
+    #[serde(skip)]
+    AccountSignedIn {
+        tokens: Tokens,
+        user: GitHubAuthenticatedUserResponse,
+    },
+    #[serde(skip)]
+    RateLimited {
+        reset_at: DateTime<Utc>,
+        retry: Box<Event>,
+    },
+    #[serde(skip)]
+    RateLimitElapsed {
+        retry: Box<Event>,
+    },
+    #[serde(skip)]
+    RateLimitUpdated(RateLimit),
+    #[serde(skip)]
+    SetTokensInStore {
+        login: String,
+        tokens: Tokens,
+    },
     #[serde(skip)]
     GetTokensFromStore,
     #[serde(skip)]
     GotTokensFromStore(Option<Tokens>),
     #[serde(skip)]
     GetTokensFromGitHub {
-        code: Option<String>,
+        code: Option<Secret>,
+        code_verifier: Secret,
     },
     #[serde(skip)]
     GotTokensFromGitHub(Tokens),
     #[serde(skip)]
+    GotSettingsFromStore {
+        login: String,
+        settings: Option<WatchHistorySettings>,
+    },
+    #[serde(skip)]
+    SettingsValidated(WatchHistorySettings),
+    #[serde(skip)]
+    SettingsValidationFailed(AppError),
+    #[serde(skip)]
     GetGithubUser,
     #[serde(skip)]
     GotGitHubUser(GitHubAuthenticatedUserResponse),
@@ -68,7 +361,62 @@ pub enum Event {
         user_info: UserInfo,
     },
     #[serde(skip)]
-    GotWatchHistoryFile(String),
+    GetCachedWatchHistory {
+        login: String,
+    },
+    #[serde(skip)]
+    GotCachedWatchHistory {
+        login: String,
+        cache: Option<CachedWatchHistory>,
+    },
+    #[serde(skip)]
+    GotWatchHistoryFile {
+        login: String,
+        file: GitHubConditionalResponse<String>,
+    },
+    #[serde(skip)]
+    SaveFilmChange(FilmChange),
+    #[serde(skip)]
+    GetChangeQueueFromStore {
+        login: String,
+    },
+    #[serde(skip)]
+    GotChangeQueueFromStore {
+        login: String,
+        queue: Vec<QueuedChange>,
+    },
+    #[serde(skip)]
+    ProcessChangeQueue,
+    #[serde(skip)]
+    GetWatchHistoryFileForChange {
+        user_info: UserInfo,
+        id: String,
+        change: FilmChange,
+        retries_remaining: u8,
+    },
+    #[serde(skip)]
+    GotWatchHistoryFileForChange {
+        user_info: UserInfo,
+        id: String,
+        change: FilmChange,
+        file: GitHubFile,
+        retries_remaining: u8,
+    },
+    #[serde(skip)]
+    WatchHistoryFileSaved {
+        id: String,
+        file: String,
+    },
+    #[serde(skip)]
+    WatchHistoryFileMergeConflicted {
+        id: String,
+        conflicts: Vec<MergeConflict>,
+    },
+    #[serde(skip)]
+    WatchHistoryFileSaveFailed {
+        id: String,
+        error: AppError,
+    },
 
     // Lifecycle events
     #[serde(skip)]
@@ -84,6 +432,7 @@ pub enum Effect {
     Render(RenderOperation),
     Http(HttpRequest),
     Redirect(RedirectOperation),
+    Delay(DelayOperation),
     KeyValue(KeyValueOperation),
 }
 
@@ -91,15 +440,43 @@ pub enum Effect {
 pub struct App;
 
 trait IntoEvent<T> {
-    fn into_event(self, map: fn(T) -> Event) -> Event;
+    fn into_event(self, map: impl FnOnce(T) -> Event, retry: Event) -> Event;
+
+    fn into_save_event(self, id: String, map: impl FnOnce(T) -> Event) -> Event;
 }
 
 impl<T> IntoEvent<T> for Result<T, GitHubApiError> {
-    fn into_event(self, map: fn(T) -> Event) -> Event {
+    fn into_event(self, map: impl FnOnce(T) -> Event, retry: Event) -> Event {
         self.map_or_else(
             |err| match err {
-                err @ GitHubApiError::HttpError(_) => panic!("{:?}", err),
                 GitHubApiError::ReAuthenticationRequired => Event::RedirectToLogin,
+                GitHubApiError::RateLimited { reset_at } => {
+                    warn!("GitHub rate limit hit, resets at {}", reset_at);
+                    Event::RateLimited {
+                        reset_at,
+                        retry: Box::new(retry),
+                    }
+                }
+                err => {
+                    error!("GitHub request failed: {:?}", err);
+                    Event::ErrorOccurred {
+                        error: err.into(),
+                        retry: Box::new(retry),
+                    }
+                }
+            },
+            map,
+        )
+    }
+
+    fn into_save_event(self, id: String, map: impl FnOnce(T) -> Event) -> Event {
+        self.map_or_else(
+            |err| {
+                error!("Saving watch history failed: {:?}", err);
+                Event::WatchHistoryFileSaveFailed {
+                    id,
+                    error: err.into(),
+                }
             },
             map,
         )
@@ -116,17 +493,333 @@ impl crux_core::App for App {
         info!("Event handling started: {:?}", msg);
 
         match msg {
-            Event::InitialLoad => render().and(Command::event(Event::GetGithubUser)),
-            Event::SetTokensInStore(store) => {
-                render().and(model.services.token_store.set_tokens(store).build())
+            Event::InitialLoad => render().and(Command::event(Event::GetAccountsFromStore)),
+            Event::GetAccountsFromStore => model
+                .services
+                .account_store
+                .get_accounts()
+                .then_send(Event::GotAccountsFromStore),
+            Event::GotAccountsFromStore(accounts) => {
+                model.accounts = accounts;
+
+                match model.accounts.active.clone() {
+                    Some(login) => render().and(
+                        model
+                            .load_account_from_store(login)
+                            .then(Command::event(Event::GetGithubUser)),
+                    ),
+                    None => render().and(
+                        model
+                            .services
+                            .token_store
+                            .get_legacy_tokens()
+                            .then_send(Event::GotLegacyTokensFromStore),
+                    ),
+                }
+            }
+            // Whoever was signed in before accounts were keyed by login is moved to their account
+            // rather than signed out, which needs a working token to ask GitHub who they are.
+            Event::GotLegacyTokensFromStore(None) => Command::done(),
+            Event::GotLegacyTokensFromStore(Some(tokens)) => {
+                let now = model.services.clock.now();
+
+                if tokens.access_token.is_valid_at(now) {
+                    return Command::event(Event::OnTokensLoaded {
+                        tokens,
+                        suppress_store: false,
+                    });
+                }
+
+                match tokens
+                    .refresh_token
+                    .filter(|refresh_token| refresh_token.is_valid_at(now))
+                {
+                    Some(refresh_token) => model
+                        .services
+                        .github_client
+                        .refresh_tokens(&refresh_token)
+                        .then_send(|x| {
+                            x.into_event(
+                                |tokens| Event::OnTokensLoaded {
+                                    tokens,
+                                    suppress_store: false,
+                                },
+                                Event::GetAccountsFromStore,
+                            )
+                        }),
+                    None => model.services.token_store.remove_legacy_tokens().build(),
+                }
             }
-            Event::GetTokensFromStore => render().and(
+            Event::GotSettingsFromStore { login, settings } => {
+                if !model.accounts.is_active(&login) {
+                    return Command::done();
+                }
+
+                model.settings = settings.unwrap_or_default();
+
+                render()
+                    .and(Command::event(Event::GetCachedWatchHistory {
+                        login: login.clone(),
+                    }))
+                    .and(Command::event(Event::GetChangeQueueFromStore { login }))
+            }
+            Event::GetCachedWatchHistory { login } => model
+                .services
+                .watch_history_cache_store
+                .get_cache(&login)
+                .then_send(move |cache| Event::GotCachedWatchHistory { login, cache }),
+            Event::GotCachedWatchHistory { login, cache } => {
+                // The network response won the race, or the cache belongs to an account or
+                // repository that is no longer being shown.
+                if !model.accounts.is_active(&login) || model.freshness == Some(Freshness::Fresh) {
+                    return Command::done();
+                }
+
+                let Some(cache) = cache.filter(|cache| cache.settings == model.settings) else {
+                    return Command::done();
+                };
+
+                model.films = cache.films;
+                model.parse_report = cache.parse_report;
+                model.watch_history = Some(cache.content);
+                model.watch_history_etag = cache.etag;
+                model.freshness = Some(Freshness::Stale {
+                    fetched_at: cache.fetched_at,
+                });
+
+                render()
+            }
+            Event::AddAccountButtonClicked => {
+                model.login_error = None;
+                render().and(Command::event(Event::RedirectToLogin))
+            }
+            Event::SwitchAccount(login) => {
+                if model.accounts.is_active(&login) || !model.accounts.switch(&login) {
+                    return render();
+                }
+
+                model.clear_account_state();
+
+                render().and(
+                    model
+                        .services
+                        .account_store
+                        .set_accounts(model.accounts.clone())
+                        .build()
+                        .and(model.load_account_from_store(login))
+                        .then(Command::event(Event::GetGithubUser)),
+                )
+            }
+            Event::RemoveAccount(login) => {
+                let was_active = model.accounts.is_active(&login);
+                model.accounts.remove(&login);
+
+                let reload = if was_active {
+                    model.clear_account_state();
+
+                    match model.accounts.active.clone() {
+                        Some(login) => model
+                            .load_account_from_store(login)
+                            .then(Command::event(Event::GetGithubUser)),
+                        None => Command::done(),
+                    }
+                } else {
+                    Command::done()
+                };
+
+                render().and(
+                    model
+                        .services
+                        .token_store
+                        .remove_tokens(&login)
+                        .build()
+                        .and(
+                            model
+                                .services
+                                .watch_history_cache_store
+                                .remove_cache(&login)
+                                .build(),
+                        )
+                        .and(
+                            model
+                                .services
+                                .change_queue_store
+                                .remove_queue(&login)
+                                .build(),
+                        )
+                        .and(
+                            model
+                                .services
+                                .settings_store
+                                .remove_settings(&login)
+                                .build(),
+                        )
+                        .and(model.services.etag_store.remove_responses(&login).build())
+                        .then(
+                            model
+                                .services
+                                .account_store
+                                .set_accounts(model.accounts.clone())
+                                .build(),
+                        )
+                        .then(reload),
+                )
+            }
+            Event::AccountSignedIn { tokens, user } => {
+                let load_from_store = if !model.accounts.is_active(&user.login) {
+                    model.clear_account_state();
+                    model.load_account_from_store(user.login.clone())
+                } else {
+                    Command::done()
+                };
+
+                model.accounts.add(user.login.clone());
+
+                render().and(
+                    load_from_store
+                        .and(
+                            Command::event(Event::SetTokensInStore {
+                                login: user.login.clone(),
+                                tokens,
+                            })
+                            .then(
+                                model
+                                    .services
+                                    .account_store
+                                    .set_accounts(model.accounts.clone())
+                                    .build(),
+                            ),
+                        )
+                        .and(model.services.token_store.remove_legacy_tokens().build())
+                        .then(Command::event(Event::GotGitHubUser(user))),
+                )
+            }
+            Event::UpdateSettings(settings) => {
+                if let Err(message) = settings.rating_scale.validate() {
+                    model.settings_status = Some(SaveStatus::Failed(AppError::Validation(message)));
+                    return render();
+                }
+
+                let Some(owner) = model
+                    .user_info
+                    .as_ref()
+                    .map(|user_info| settings.owner_or(&user_info.login))
+                    .or(settings.owner.clone())
+                else {
+                    model.settings_status = Some(SaveStatus::Failed(AppError::Unauthorized));
+                    return render();
+                };
+
+                model.settings_status = Some(SaveStatus::Pending);
+
+                render().and(
+                    model
+                        .services
+                        .github_client
+                        .get_file_contents_with_metadata(
+                            owner,
+                            settings.repo.clone(),
+                            settings.path.clone(),
+                            settings.git_ref.clone(),
+                        )
+                        .then_send(move |x| match x {
+                            Ok(_) => Event::SettingsValidated(settings),
+                            Err(GitHubApiError::ReAuthenticationRequired) => Event::RedirectToLogin,
+                            Err(err) => Event::SettingsValidationFailed(err.into()),
+                        }),
+                )
+            }
+            Event::ResetSettings => render().and(Command::event(Event::SettingsValidated(
+                WatchHistorySettings::default(),
+            ))),
+            Event::SettingsValidated(settings) => {
+                if model.settings != settings {
+                    model.freshness = None;
+                    model.watch_history_etag = None;
+                }
+
+                model.settings = settings.clone();
+                model.settings_status = Some(SaveStatus::Saved);
+
+                let reload = match model.user_info.clone() {
+                    Some(user_info) => Command::event(Event::GetWatchHistoryFile { user_info }),
+                    None => Command::done(),
+                };
+
+                let store = match model.accounts.active.as_deref() {
+                    Some(login) => model
+                        .services
+                        .settings_store
+                        .set_settings(login, settings)
+                        .build(),
+                    None => Command::done(),
+                };
+
+                render().and(store.then(reload))
+            }
+            Event::ErrorOccurred { error, retry } => {
+                model.error = Some(error);
+                model.retry_event = Some(*retry);
+                render()
+            }
+            Event::RateLimited { reset_at, retry } => {
+                let wait = (reset_at - model.services.clock.now())
+                    .to_std()
+                    .unwrap_or_default();
+
+                if wait > MAX_AUTOMATIC_BACKOFF {
+                    return Command::event(Event::ErrorOccurred {
+                        error: AppError::RateLimited { reset_at },
+                        retry,
+                    });
+                }
+
+                model.sync_paused_until = Some(reset_at);
+
+                render().and(delay(wait).then_send(move |()| Event::RateLimitElapsed { retry }))
+            }
+            Event::RateLimitElapsed { retry } => {
+                model.sync_paused_until = None;
+                render().and(Command::event(*retry))
+            }
+            Event::RateLimitUpdated(rate_limit) => {
+                model.rate_limit = Some(rate_limit);
+                render()
+            }
+            Event::DismissError => {
+                model.error = None;
+                model.retry_event = None;
+                render()
+            }
+            Event::RetryAfterError => {
+                model.error = None;
+
+                match model.retry_event.take() {
+                    Some(retry) => render().and(Command::event(retry)),
+                    None => render(),
+                }
+            }
+            Event::SettingsValidationFailed(reason) => {
+                model.settings_status = Some(SaveStatus::Failed(reason));
+                render()
+            }
+            Event::SetTokensInStore { login, tokens } => render().and(
                 model
                     .services
                     .token_store
-                    .get_tokens()
-                    .then_send(Event::GotTokensFromStore),
+                    .set_tokens(&login, tokens)
+                    .build(),
             ),
+            Event::GetTokensFromStore => match model.accounts.active.as_deref() {
+                Some(login) => render().and(
+                    model
+                        .services
+                        .token_store
+                        .get_tokens(login)
+                        .then_send(Event::GotTokensFromStore),
+                ),
+                None => render(),
+            },
             Event::GotTokensFromStore(Some(store)) => {
                 render().and(Command::event(Event::OnTokensLoaded {
                     tokens: store,
@@ -134,10 +827,134 @@ impl crux_core::App for App {
                 }))
             }
             Event::GotTokensFromStore(None) => render(),
-            Event::LoginButtonClicked => render().and(Command::event(Event::RedirectToLogin)),
-            Event::LogoutButtonClicked => {
-                model.user_info = None;
-                render().then(model.services.token_store.remove_tokens().build())
+            Event::LoginButtonClicked => {
+                model.login_error = None;
+                render().and(Command::event(Event::RedirectToLogin))
+            }
+            Event::LoginWithToken(token) => {
+                model.login_error = None;
+
+                let tokens = Tokens::personal_access_token(token.expose().trim());
+
+                render().and(
+                    model
+                        .services
+                        .github_client
+                        .get_authenticated_user_with_token(tokens.access_token.clone())
+                        .then_send(move |x| match x {
+                            Ok(user) => Event::AccountSignedIn { tokens, user },
+                            Err(GitHubApiError::HttpError(HttpError::Http {
+                                code: StatusCode::Unauthorized,
+                                ..
+                            })) => Event::LoginFailed(LoginError::InvalidToken),
+                            // Reported on the login screen rather than as a retryable error, so the
+                            // token isn't kept in the model to be sent again.
+                            Err(err) => Event::LoginFailed(LoginError::RequestFailed(err.into())),
+                        }),
+                )
+            }
+            Event::SetLoginMode(login_mode) => {
+                model.login_mode = login_mode;
+                render()
+            }
+            Event::StartDeviceLogin => render().and(
+                model
+                    .services
+                    .github_client
+                    .request_device_code()
+                    .then_send(|x| x.into_event(Event::GotDeviceCode, Event::StartDeviceLogin)),
+            ),
+            Event::GotDeviceCode(device_code) => {
+                model.device_code = Some(device_code.device_code.clone());
+                model.device_login = Some(DeviceLogin {
+                    user_code: device_code.user_code,
+                    verification_uri: device_code.verification_uri,
+                });
+
+                render().and(Command::event(Event::PollDeviceLogin {
+                    device_code: device_code.device_code,
+                    interval: device_code.interval,
+                }))
+            }
+            Event::PollDeviceLogin {
+                device_code,
+                interval,
+            } => {
+                let poll = model
+                    .services
+                    .github_client
+                    .poll_device_token(device_code.clone());
+
+                delay(Duration::from_secs(interval))
+                    .then_request(move |()| poll)
+                    .then_send(move |x| {
+                        x.into_event(
+                            |poll| Event::GotDeviceLoginPoll {
+                                device_code: device_code.clone(),
+                                interval,
+                                poll,
+                            },
+                            Event::PollDeviceLogin {
+                                device_code: device_code.clone(),
+                                interval,
+                            },
+                        )
+                    })
+            }
+            Event::GotDeviceLoginPoll {
+                device_code,
+                interval,
+                poll,
+            } => {
+                // A poll that was already in flight when the login was cancelled or restarted.
+                if model.device_code.as_ref() != Some(&device_code) {
+                    return Command::done();
+                }
+
+                let next = match poll {
+                    GitHubDeviceTokenPoll::Pending => Event::PollDeviceLogin {
+                        device_code,
+                        interval,
+                    },
+                    GitHubDeviceTokenPoll::SlowDown {
+                        interval: new_interval,
+                    } => Event::PollDeviceLogin {
+                        device_code,
+                        interval: new_interval.max(interval + 5),
+                    },
+                    GitHubDeviceTokenPoll::Complete(tokens) => Event::OnTokensLoaded {
+                        tokens,
+                        suppress_store: false,
+                    },
+                    GitHubDeviceTokenPoll::Expired => {
+                        Event::LoginFailed(LoginError::DeviceCodeExpired)
+                    }
+                    GitHubDeviceTokenPoll::Denied => {
+                        Event::LoginFailed(LoginError::Denied("access_denied".to_string()))
+                    }
+                    GitHubDeviceTokenPoll::Failed(error) => {
+                        Event::LoginFailed(LoginError::Denied(error))
+                    }
+                };
+
+                if !matches!(next, Event::PollDeviceLogin { .. }) {
+                    model.device_code = None;
+                    model.device_login = None;
+                }
+
+                render().and(Command::event(next))
+            }
+            Event::CancelDeviceLogin => {
+                model.device_code = None;
+                model.device_login = None;
+                render()
+            }
+            Event::LogoutButtonClicked => match model.accounts.active.clone() {
+                Some(login) => render().and(Command::event(Event::RemoveAccount(login))),
+                None => render(),
+            },
+            Event::RedirectToLogin if model.login_mode == LoginMode::DeviceFlow => {
+                render().and(Command::event(Event::StartDeviceLogin))
             }
             Event::RedirectToLogin => {
                 #[derive(Serialize)]
@@ -145,44 +962,123 @@ impl crux_core::App for App {
                     client_id: String,
                     redirect_uri: String,
                     state: String,
+                    code_challenge: String,
+                    code_challenge_method: String,
                 }
 
                 let mut rng = StdRng::from_os_rng();
                 let state = Alphanumeric.sample_string(&mut rng, 16);
+                let code_verifier = Secret::new(Alphanumeric.sample_string(&mut rng, 64));
+
+                let pending_login =
+                    PendingLogin::new(state, code_verifier, model.services.clock.now());
 
                 let mut url = GITHUB_OAUTH_AUTHORIZE_URL.clone();
 
                 let query_params = QueryParams {
                     client_id: model.services.config.github.client_id.clone(),
                     redirect_uri: model.services.config.github.redirect_uri.clone(),
-                    state,
+                    state: pending_login.state.clone(),
+                    code_challenge: pending_login.code_challenge(),
+                    code_challenge_method: "S256".to_string(),
                 };
 
                 url.set_query(serde_qs::to_string(&query_params).ok().as_deref());
 
-                redirect(url)
+                model
+                    .services
+                    .pending_login_store
+                    .set_pending_login(pending_login)
+                    .build()
+                    .then(redirect(url))
             }
             Event::CallbackReceived(url) => {
-                let code = Url::parse(&url)
-                    .expect("invalid callback URL")
-                    .query_pairs()
-                    .find_map(|(key, val)| {
-                        if key == "code" {
+                let Ok(url) = Url::parse(&url) else {
+                    return Command::event(Event::LoginFailed(LoginError::InvalidCallback));
+                };
+
+                let query_param = |name: &str| {
+                    url.query_pairs().find_map(|(key, val)| {
+                        if key == name {
                             Some(val.into_owned())
                         } else {
                             None
                         }
-                    });
+                    })
+                };
+
+                let code = query_param("code").map(Secret::new);
+                let state = query_param("state");
+
+                if let Some(error) = query_param("error") {
+                    let description = query_param("error_description").unwrap_or(error);
+                    return render()
+                        .and(
+                            model
+                                .services
+                                .pending_login_store
+                                .remove_pending_login()
+                                .build(),
+                        )
+                        .and(Command::event(Event::LoginFailed(LoginError::Denied(
+                            description,
+                        ))));
+                }
 
-                render().and(Command::event(Event::GetTokensFromGitHub { code }))
+                render().and(
+                    model
+                        .services
+                        .pending_login_store
+                        .get_pending_login()
+                        .then_send(move |pending_login| Event::GotPendingLogin {
+                            code,
+                            state,
+                            pending_login,
+                        }),
+                )
             }
-            Event::GetTokensFromGitHub { code: None } => render(),
-            Event::GetTokensFromGitHub { code: Some(code) } => render().and(
+            Event::GotPendingLogin {
+                code,
+                state,
+                pending_login,
+            } => {
+                let next = match pending_login {
+                    Some(pending_login) => {
+                        match pending_login.verify(state.as_deref(), model.services.clock.now()) {
+                            Ok(()) => Event::GetTokensFromGitHub {
+                                code,
+                                code_verifier: pending_login.code_verifier,
+                            },
+                            Err(err) => Event::LoginFailed(err),
+                        }
+                    }
+                    None => Event::LoginFailed(LoginError::NoPendingLogin),
+                };
+
+                model
+                    .services
+                    .pending_login_store
+                    .remove_pending_login()
+                    .build()
+                    .then(Command::event(next))
+            }
+            Event::LoginFailed(error) => {
+                warn!("Login failed: {:?}", error);
+                model.login_error = Some(error);
+                render()
+            }
+            Event::GetTokensFromGitHub { code: None, .. } => render(),
+            Event::GetTokensFromGitHub {
+                code: Some(code),
+                code_verifier,
+            } => render().and(
                 model
                     .services
                     .github_client
-                    .get_access_token_from_code(code)
-                    .then_send(|x| x.into_event(Event::GotTokensFromGitHub)),
+                    .get_access_token_from_code(code, code_verifier)
+                    .then_send(|x| {
+                        x.into_event(Event::GotTokensFromGitHub, Event::RedirectToLogin)
+                    }),
             ),
             Event::GotTokensFromGitHub(store) => {
                 render().and(Command::event(Event::OnTokensLoaded {
@@ -195,7 +1091,9 @@ impl crux_core::App for App {
                     .services
                     .github_client
                     .get_authenticated_user()
-                    .then_send(|x| x.into_event(Event::GotGitHubUser)),
+                    .then_send(|x| {
+                        x.into_event(|res| Event::GotGitHubUser(res.body), Event::GetGithubUser)
+                    }),
             ),
             Event::GotGitHubUser(user) => {
                 let user_info = UserInfo {
@@ -206,35 +1104,921 @@ impl crux_core::App for App {
 
                 model.user_info = Some(user_info.clone());
 
-                render().then(Command::event(Event::GetWatchHistoryFile { user_info }))
+                render()
+                    .then(Command::event(Event::GetWatchHistoryFile { user_info }))
+                    .and(Command::event(Event::ProcessChangeQueue))
             }
             Event::OnTokensLoaded {
                 tokens,
                 suppress_store,
-            } => render().and(
-                if !suppress_store {
-                    Command::event(Event::SetTokensInStore(tokens))
-                } else {
-                    Command::done()
+            } => {
+                if suppress_store {
+                    return render().and(Command::event(Event::GetGithubUser));
                 }
-                .then(Command::event(Event::GetGithubUser)),
-            ),
+
+                // New tokens are stored against the account they belong to, which is only known
+                // once GitHub has told us who they were issued for.
+                render().and(
+                    model
+                        .services
+                        .github_client
+                        .get_authenticated_user_with_token(tokens.access_token.clone())
+                        .then_send(move |x| {
+                            x.into_event(
+                                |user| Event::AccountSignedIn {
+                                    tokens: tokens.clone(),
+                                    user,
+                                },
+                                Event::OnTokensLoaded {
+                                    tokens: tokens.clone(),
+                                    suppress_store,
+                                },
+                            )
+                        }),
+                )
+            }
             Event::GetWatchHistoryFile { user_info } => model
                 .services
                 .github_client
-                .get_file_contents(user_info.login, "notes", "watch_history.md")
-                .then_send(|x| x.into_event(Event::GotWatchHistoryFile)),
-            Event::GotWatchHistoryFile(file) => {
-                model.films = parse_films_from_markdown(file);
+                .get_file_contents(
+                    model.settings.owner_or(&user_info.login),
+                    model.settings.repo.clone(),
+                    model.settings.path.clone(),
+                    model.settings.git_ref.clone(),
+                    model.cached_watch_history(),
+                )
+                .then_send(move |x| {
+                    let login = user_info.login.clone();
+                    x.into_event(
+                        |file| Event::GotWatchHistoryFile { login, file },
+                        Event::GetWatchHistoryFile { user_info },
+                    )
+                }),
+            Event::GotWatchHistoryFile { login, file } => {
+                // The account was switched while the file was being fetched.
+                if !model.accounts.is_active(&login) {
+                    return Command::done();
+                }
+
+                // An unchanged file only needs parsing if nothing has been shown from the cache.
+                if file.changed || model.freshness.is_none() {
+                    (model.films, model.parse_report) =
+                        parse_films_from_markdown(file.body.clone(), &model.settings.rating_scale);
+                }
+
+                model.watch_history = Some(file.body);
+                model.watch_history_etag = file.etag;
+
+                model.freshness = Some(Freshness::Fresh);
+
+                render().and(model.cache_watch_history(&login))
+            }
+            Event::AddFilm {
+                title,
+                rating,
+                year_watched,
+                month_of_year_watched,
+                day_of_month_watched,
+                rewatch,
+                tags,
+                companions,
+                notes,
+            } => {
+                if let Err(err) = check_tags(&tags, &companions) {
+                    model.save_status = Some(SaveStatus::Failed(AppError::Validation(err)));
+                    return render();
+                }
+
+                let today = model.services.clock.today();
+                let year_watched = year_watched.unwrap_or(today.year());
+                let month_of_year_watched = month_of_year_watched
+                    .unwrap_or_else(|| MonthOfYear::try_from(today.month()).expect("valid month"));
+
+                // A day the month doesn't have would be written to the file and then not parse.
+                if let Some(Err(err)) = day_of_month_watched
+                    .map(|day| Date::new(year_watched, month_of_year_watched.number(), day))
+                {
+                    model.save_status =
+                        Some(SaveStatus::Failed(AppError::Validation(err.to_string())));
+                    return render();
+                }
+
+                let film = WatchedFilm {
+                    title,
+                    rating,
+                    year_watched,
+                    month_of_year_watched,
+                    day_of_month_watched,
+                    rewatch,
+                    tags,
+                    companions,
+                    notes,
+                };
+
+                render().and(Command::event(Event::SaveFilmChange(FilmChange::Add(film))))
+            }
+            Event::EditFilm {
+                film,
+                title,
+                rating,
+                tags,
+                companions,
+                notes,
+            } => {
+                if let Err(err) = check_tags(&tags, &companions) {
+                    model.save_status = Some(SaveStatus::Failed(AppError::Validation(err)));
+                    return render();
+                }
+
+                render().and(Command::event(Event::SaveFilmChange(FilmChange::Edit {
+                    film,
+                    title,
+                    rating,
+                    tags,
+                    companions,
+                    notes,
+                })))
+            }
+            Event::DeleteFilm(film) => render().and(Command::event(Event::SaveFilmChange(
+                FilmChange::Delete(film),
+            ))),
+            Event::SelectFilmTitle(title) => {
+                model.selected_title = Some(title);
+
+                render()
+            }
+            Event::ClearSelectedFilmTitle => {
+                model.selected_title = None;
+
+                render()
+            }
+            Event::SetFilmFilter(filter) => {
+                model.film_filter = filter;
+
+                render()
+            }
+            Event::SetFilmGrouping(grouping) => {
+                model.film_grouping = grouping;
+
+                render()
+            }
+            Event::SaveFilmChange(change) => {
+                // The change is kept for the account even before its user has loaded, and is sent
+                // once it has.
+                if model.accounts.active.is_none() {
+                    model.save_status = Some(SaveStatus::Failed(AppError::Unauthorized));
+                    return render();
+                }
+
+                let base = model.local_watch_history();
+                model.change_queue.push(QueuedChange::new(change, base));
+                model.save_status = Some(SaveStatus::Pending);
+
+                render()
+                    .and(model.store_change_queue())
+                    .then(Command::event(Event::ProcessChangeQueue))
+            }
+            Event::GetChangeQueueFromStore { login } => model
+                .services
+                .change_queue_store
+                .get_queue(&login)
+                .then_send(move |queue| Event::GotChangeQueueFromStore { login, queue }),
+            Event::GotChangeQueueFromStore { login, queue } => {
+                if !model.accounts.is_active(&login) {
+                    return Command::done();
+                }
+
+                // Changes queued since startup go after the ones that were already waiting. The
+                // queue may have been read before, so changes already in it aren't added again.
+                let queued_since_load = std::mem::take(&mut model.change_queue);
+                model.change_queue = queue
+                    .into_iter()
+                    .filter(|stored| {
+                        !queued_since_load
+                            .iter()
+                            .any(|queued| queued.id == stored.id)
+                    })
+                    .chain(queued_since_load)
+                    .collect();
+                model.change_queue_loaded = true;
+
+                if model.change_queue.is_empty() {
+                    return Command::done();
+                }
+
+                render()
+                    .and(model.store_change_queue())
+                    .and(Command::event(Event::ProcessChangeQueue))
+            }
+            Event::ConnectivityRestored => render().and(Command::event(Event::ProcessChangeQueue)),
+            Event::RetryQueuedChanges => {
+                for queued in &mut model.change_queue {
+                    queued.status = QueuedChangeStatus::Pending;
+                }
+
+                render()
+                    .and(model.store_change_queue())
+                    .then(Command::event(Event::ProcessChangeQueue))
+            }
+            Event::DiscardQueuedChange(id) => {
+                if model.change_in_flight.as_ref() == Some(&id) {
+                    return render();
+                }
+
+                model.change_queue.retain(|queued| queued.id != id);
+
+                render().and(model.store_change_queue())
+            }
+            Event::ResolveQueuedChangeConflict { id, keep } => {
+                let Some(queued) = model.change_queue.iter_mut().find(|queued| queued.id == id)
+                else {
+                    return render();
+                };
+
+                queued.resolution = Some(keep);
+                queued.status = QueuedChangeStatus::Pending;
+
+                render()
+                    .and(model.store_change_queue())
+                    .then(Command::event(Event::ProcessChangeQueue))
+            }
+            Event::ProcessChangeQueue => {
+                if model.change_in_flight.is_some() {
+                    return Command::done();
+                }
+
+                let Some(user_info) = model.user_info.clone() else {
+                    return Command::done();
+                };
+
+                let Some(queued) = model
+                    .change_queue
+                    .iter()
+                    .find(|queued| queued.status == QueuedChangeStatus::Pending)
+                    .cloned()
+                else {
+                    if model.save_status == Some(SaveStatus::Pending) {
+                        model.save_status = Some(SaveStatus::Saved);
+                    }
+
+                    return render();
+                };
+
+                model.change_in_flight = Some(queued.id.clone());
+                model.save_status = Some(SaveStatus::Pending);
+
+                render().and(Command::event(Event::GetWatchHistoryFileForChange {
+                    user_info,
+                    id: queued.id,
+                    change: queued.change,
+                    retries_remaining: MAX_SAVE_RETRIES,
+                }))
+            }
+            Event::GetWatchHistoryFileForChange {
+                user_info,
+                id,
+                change,
+                retries_remaining,
+            } => model
+                .services
+                .github_client
+                .get_file_contents_with_metadata(
+                    model.settings.owner_or(&user_info.login),
+                    model.settings.repo.clone(),
+                    model.settings.path.clone(),
+                    model.settings.git_ref.clone(),
+                )
+                .then_send(move |x| {
+                    x.into_save_event(id.clone(), |file| Event::GotWatchHistoryFileForChange {
+                        user_info,
+                        id,
+                        change,
+                        file,
+                        retries_remaining,
+                    })
+                }),
+            Event::GotWatchHistoryFileForChange {
+                user_info,
+                id,
+                change,
+                file,
+                retries_remaining,
+            } => {
+                let Some(queued) = model.change_queue.iter().find(|queued| queued.id == id) else {
+                    model.change_in_flight = None;
+                    return Command::done();
+                };
+
+                let scale = &model.settings.rating_scale;
+
+                // Without a base, or if the file hasn't changed on GitHub since, the change is
+                // applied to the remote file directly.
+                let merged = match queued
+                    .base
+                    .as_deref()
+                    .filter(|&base| base != file.content)
+                    .and_then(|base| {
+                        apply_change_to_markdown(base, &change, scale).map(|local| (base, local))
+                    }) {
+                    Some((base, local)) => {
+                        let merge =
+                            merge_markdown(base, &local, &file.content, queued.resolution, scale);
+
+                        if !merge.conflicts.is_empty() {
+                            return Command::event(Event::WatchHistoryFileMergeConflicted {
+                                id,
+                                conflicts: merge.conflicts,
+                            });
+                        }
+
+                        Some(merge.markdown)
+                    }
+                    None => apply_change_to_markdown(file.content.clone(), &change, scale),
+                };
+
+                let Some(markdown) = merged else {
+                    return Command::event(Event::WatchHistoryFileSaveFailed {
+                        id,
+                        error: AppError::NotFound("Film not found in watch history".to_string()),
+                    });
+                };
+
+                // The same change was already made on GitHub, so there is nothing to commit.
+                if markdown == file.content {
+                    return Command::event(Event::WatchHistoryFileSaved { id, file: markdown });
+                }
+
+                model
+                    .services
+                    .github_client
+                    .put_file_contents(PutFileContents {
+                        owner: model.settings.owner_or(&user_info.login),
+                        repo: model.settings.repo.clone(),
+                        path: model.settings.path.clone(),
+                        content: markdown.clone(),
+                        message: change.commit_message(),
+                        sha: file.sha,
+                        branch: model.settings.git_ref.clone(),
+                    })
+                    .then_send(move |x| match x {
+                        Err(GitHubApiError::ShaConflict) if retries_remaining > 0 => {
+                            Event::GetWatchHistoryFileForChange {
+                                user_info,
+                                id,
+                                change,
+                                retries_remaining: retries_remaining - 1,
+                            }
+                        }
+                        x => x.into_save_event(id.clone(), |_| Event::WatchHistoryFileSaved {
+                            id,
+                            file: markdown,
+                        }),
+                    })
+            }
+            Event::WatchHistoryFileSaved { id, file } => {
+                // The account was switched while the change was being committed.
+                if model.change_in_flight.as_ref() != Some(&id) {
+                    return Command::done();
+                }
+
+                model.change_in_flight = None;
+                model.change_queue.retain(|queued| queued.id != id);
+
+                (model.films, model.parse_report) =
+                    parse_films_from_markdown(file.clone(), &model.settings.rating_scale);
+                model.watch_history = Some(file);
+                model.watch_history_etag = None;
+                model.freshness = Some(Freshness::Fresh);
+
+                let cache = match model.accounts.active.clone() {
+                    Some(login) => model.cache_watch_history(&login),
+                    None => Command::done(),
+                };
+
                 render()
+                    .and(cache)
+                    .and(model.store_change_queue())
+                    .then(Command::event(Event::ProcessChangeQueue))
+            }
+            Event::WatchHistoryFileMergeConflicted { id, conflicts } => {
+                if model.change_in_flight.as_ref() != Some(&id) {
+                    return Command::done();
+                }
+
+                model.change_in_flight = None;
+                model.save_status = Some(SaveStatus::Failed(AppError::Conflict));
+
+                if let Some(queued) = model.change_queue.iter_mut().find(|queued| queued.id == id) {
+                    queued.status = QueuedChangeStatus::Conflicted(conflicts);
+                }
+
+                render()
+                    .and(model.store_change_queue())
+                    .then(Command::event(Event::ProcessChangeQueue))
+            }
+            Event::WatchHistoryFileSaveFailed { id, error } => {
+                if model.change_in_flight.as_ref() != Some(&id) {
+                    return Command::done();
+                }
+
+                model.change_in_flight = None;
+                model.save_status = Some(SaveStatus::Failed(error.clone()));
+
+                match error {
+                    // Held in the queue until the user has signed in again.
+                    AppError::Unauthorized => render().and(Command::event(Event::RedirectToLogin)),
+                    AppError::RateLimited { reset_at } => {
+                        render().and(Command::event(Event::RateLimited {
+                            reset_at,
+                            retry: Box::new(Event::ProcessChangeQueue),
+                        }))
+                    }
+                    // Held in the queue until connectivity is restored.
+                    error if error.is_transient() => render(),
+                    error => {
+                        if let Some(queued) =
+                            model.change_queue.iter_mut().find(|queued| queued.id == id)
+                        {
+                            queued.status = QueuedChangeStatus::Failed(error);
+                        }
+
+                        render()
+                            .and(model.store_change_queue())
+                            .then(Command::event(Event::ProcessChangeQueue))
+                    }
+                }
             }
         }
     }
 
     fn view(&self, model: &Self::Model) -> Self::ViewModel {
+        let films = model
+            .change_queue
+            .iter()
+            .filter(|queued| queued.status == QueuedChangeStatus::Pending)
+            .fold(model.films.clone(), |mut films, queued| {
+                queued.change.apply_to(&mut films);
+                films
+            })
+            .into_iter()
+            .filter(|film| {
+                model
+                    .film_filter
+                    .as_ref()
+                    .map_or(true, |filter| filter.matches(film))
+            })
+            .collect::<Vec<_>>();
+
+        let selected_film_viewings = model
+            .selected_title
+            .as_deref()
+            .map(|title| viewings_of(&films, title))
+            .unwrap_or_default();
+
         Self::ViewModel {
-            films: model.films.clone(),
+            film_summaries: FilmSummary::from_films(&films),
+            selected_film: FilmSummary::from_viewings(&selected_film_viewings),
+            selected_film_viewings,
+            film_filter: model.film_filter.clone(),
+            film_grouping: model.film_grouping.clone(),
+            film_groups: model
+                .film_grouping
+                .as_ref()
+                .map(|grouping| grouping.group(&films))
+                .unwrap_or_default(),
+            films: films.into_iter().map(WatchedFilmView::from).collect(),
+            accounts: model.accounts.logins.clone(),
             user_info: model.user_info.clone(),
+            parse_report: model.parse_report.clone(),
+            freshness: model.freshness.clone(),
+            save_status: model.save_status.clone(),
+            queued_changes: model.change_queue.clone(),
+            rate_limit: model.rate_limit.clone(),
+            sync_paused_until: model.sync_paused_until,
+            settings: model.settings.clone(),
+            settings_status: model.settings_status.clone(),
+            error: model.error.clone(),
+            login_error: model.login_error.clone(),
+            login_mode: model.login_mode.clone(),
+            device_login: model.device_login.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+    use crate::film::RatingScale;
+    use crate::tokens::Token;
+    use crux_core::App as _;
+    use jiff::tz::{offset, TimeZone};
+
+    fn add_film(year_watched: Option<i16>, month_of_year_watched: Option<MonthOfYear>) -> Event {
+        Event::AddFilm {
+            title: "Heat".to_string(),
+            rating: RatingScale::default()
+                .parse("good")
+                .ok()
+                .expect("rating on the default scale"),
+            year_watched,
+            month_of_year_watched,
+            day_of_month_watched: None,
+            rewatch: false,
+            tags: vec![],
+            companions: vec![],
+            notes: None,
+        }
+    }
+
+    fn add_film_on(year: i16, month: &str, day: i8) -> Event {
+        let mut event = add_film(Some(year), MonthOfYear::try_from(month).ok());
+
+        if let Event::AddFilm {
+            day_of_month_watched,
+            ..
+        } = &mut event
+        {
+            *day_of_month_watched = Some(day);
+        }
+
+        event
+    }
+
+    /// The year and month `AddFilm` files the entry under when sent at `now` in `time_zone`.
+    fn filed_under(now: &str, time_zone: TimeZone, event: Event) -> (i16, i8) {
+        let now = DateTime::parse_from_rfc3339(now)
+            .expect("valid date")
+            .with_timezone(&Utc);
+        let clock = FixedClock::new(now).with_time_zone(time_zone);
+        let mut model = Model::with_clock(Arc::new(clock));
+
+        match App.update(event, &mut model).events().next() {
+            Some(Event::SaveFilmChange(FilmChange::Add(film))) => {
+                (film.year_watched, film.month_of_year_watched.number())
+            }
+            event => panic!("unexpected event {event:?}"),
+        }
+    }
+
+    #[test]
+    fn changes_are_queued_before_the_user_loads() {
+        let mut model = Model::default();
+        model.accounts.add("octocat");
+
+        let save = App
+            .update(
+                add_film(Some(2025), MonthOfYear::try_from("March").ok()),
+                &mut model,
+            )
+            .events()
+            .next()
+            .expect("a change to save");
+        let mut cmd = App.update(save, &mut model);
+
+        assert!(cmd
+            .effects()
+            .all(|effect| matches!(effect, Effect::Render(_) | Effect::KeyValue(_))));
+        assert_eq!(model.change_queue.len(), 1);
+        assert_eq!(model.save_status, Some(SaveStatus::Pending));
+
+        let mut cmd = App.update(Event::ProcessChangeQueue, &mut model);
+
+        assert!(cmd.effects().next().is_none());
+        assert_eq!(model.change_in_flight, None);
+        assert_eq!(model.save_status, Some(SaveStatus::Pending));
+    }
+
+    #[test]
+    fn changes_queued_before_the_queue_loads_are_added_to_the_stored_ones() {
+        let mut model = Model::default();
+        model.accounts.add("octocat");
+
+        let stored = QueuedChange::new(FilmChange::Add(watched("Alien", &[], &[])), None);
+        let save = App
+            .update(
+                add_film(Some(2025), MonthOfYear::try_from("March").ok()),
+                &mut model,
+            )
+            .events()
+            .next()
+            .expect("a change to save");
+        let mut cmd = App.update(save, &mut model);
+
+        // Storing the queue now would replace the stored change.
+        assert!(cmd
+            .effects()
+            .all(|effect| matches!(effect, Effect::Render(_))));
+
+        let loaded = || Event::GotChangeQueueFromStore {
+            login: "octocat".to_string(),
+            queue: vec![stored.clone()],
+        };
+        let mut cmd = App.update(loaded(), &mut model);
+
+        assert!(cmd
+            .effects()
+            .any(|effect| matches!(effect, Effect::KeyValue(_))));
+        let titles = |model: &Model| -> Vec<String> {
+            model
+                .change_queue
+                .iter()
+                .map(|queued| match &queued.change {
+                    FilmChange::Add(film) => film.title.clone(),
+                    change => panic!("unexpected change {change:?}"),
+                })
+                .collect()
+        };
+        assert_eq!(titles(&model), ["Alien", "Heat"]);
+
+        let _ = App.update(loaded(), &mut model);
+        assert_eq!(titles(&model), ["Alien", "Heat"]);
+    }
+
+    #[test]
+    fn days_the_month_does_not_have_are_not_saved() {
+        let mut model = Model::default();
+        model.accounts.add("octocat");
+
+        let mut cmd = App.update(add_film_on(2025, "February", 29), &mut model);
+
+        assert!(cmd.events().next().is_none());
+        assert!(matches!(
+            model.save_status,
+            Some(SaveStatus::Failed(AppError::Validation(_)))
+        ));
+
+        let mut cmd = App.update(add_film_on(2024, "February", 29), &mut model);
+
+        assert!(matches!(
+            cmd.events().next(),
+            Some(Event::SaveFilmChange(FilmChange::Add(_)))
+        ));
+    }
+
+    #[test]
+    fn tags_that_would_not_be_read_back_are_not_saved() {
+        let mut model = Model::default();
+        model.accounts.add("octocat");
+
+        let mut event = add_film(Some(2025), MonthOfYear::try_from("March").ok());
+        if let Event::AddFilm { tags, .. } = &mut event {
+            tags.push("two words".to_string());
+        }
+        let mut cmd = App.update(event, &mut model);
+
+        assert!(cmd.events().next().is_none());
+        assert!(matches!(
+            model.save_status,
+            Some(SaveStatus::Failed(AppError::Validation(_)))
+        ));
+
+        let film = watched("Heat", &[], &[]);
+        let mut cmd = App.update(
+            Event::EditFilm {
+                title: film.title.clone(),
+                rating: film.rating.clone(),
+                tags: vec!["home".to_string()],
+                companions: vec!["@sam".to_string()],
+                notes: None,
+                film,
+            },
+            &mut model,
+        );
+
+        assert!(cmd.events().next().is_none());
+        assert!(matches!(
+            model.save_status,
+            Some(SaveStatus::Failed(AppError::Validation(_)))
+        ));
+    }
+
+    fn watched(title: &str, tags: &[&str], companions: &[&str]) -> WatchedFilm {
+        WatchedFilm {
+            title: title.to_string(),
+            rating: RatingScale::default()
+                .parse("good")
+                .ok()
+                .expect("rating on the default scale"),
+            year_watched: 2025,
+            month_of_year_watched: MonthOfYear::try_from("March").ok().expect("valid month"),
+            day_of_month_watched: None,
+            rewatch: false,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            companions: companions.iter().map(|name| name.to_string()).collect(),
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn films_are_filtered_and_grouped_by_tag_and_companion() {
+        let mut model = Model::default();
+        model.films = vec![
+            watched("Heat", &["cinema"], &["alex"]),
+            watched("Alien", &["Home"], &["alex", "sam"]),
+            watched("Klaus", &["home"], &[]),
+        ];
+
+        let titles = |model: &Model| -> Vec<String> {
+            App.view(model)
+                .films
+                .into_iter()
+                .map(|film| film.film.title)
+                .collect()
+        };
+        let groups = |model: &Model| -> Vec<(String, Vec<String>)> {
+            App.view(model)
+                .film_groups
+                .into_iter()
+                .map(|group| {
+                    let titles = group.films.into_iter().map(|film| film.title).collect();
+                    (group.name, titles)
+                })
+                .collect()
+        };
+
+        let _ = App.update(
+            Event::SetFilmFilter(Some(FilmFilter::Tag("HOME".to_string()))),
+            &mut model,
+        );
+        assert_eq!(titles(&model), ["Alien", "Klaus"]);
+
+        let _ = App.update(
+            Event::SetFilmFilter(Some(FilmFilter::Companion("alex".to_string()))),
+            &mut model,
+        );
+        assert_eq!(titles(&model), ["Heat", "Alien"]);
+
+        let _ = App.update(Event::SetFilmFilter(None), &mut model);
+        let _ = App.update(Event::SetFilmGrouping(Some(FilmGrouping::Tag)), &mut model);
+        assert_eq!(
+            groups(&model),
+            [
+                ("cinema".to_string(), vec!["Heat".to_string()]),
+                (
+                    "Home".to_string(),
+                    vec!["Alien".to_string(), "Klaus".to_string()]
+                ),
+            ]
+        );
+
+        let _ = App.update(
+            Event::SetFilmGrouping(Some(FilmGrouping::Companion)),
+            &mut model,
+        );
+        assert_eq!(
+            groups(&model),
+            [
+                (
+                    "alex".to_string(),
+                    vec!["Heat".to_string(), "Alien".to_string()]
+                ),
+                ("sam".to_string(), vec!["Alien".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn the_view_gives_the_date_of_dated_entries() {
+        let mut model = Model::default();
+        model.accounts.add("octocat");
+
+        for event in [
+            add_film_on(2025, "March", 14),
+            add_film(Some(2025), MonthOfYear::try_from("March").ok()),
+        ] {
+            if let Some(Event::SaveFilmChange(FilmChange::Add(film))) =
+                App.update(event, &mut model).events().next()
+            {
+                model.films.push(film);
+            }
         }
+
+        let dates: Vec<_> = App
+            .view(&model)
+            .films
+            .into_iter()
+            .map(|film| film.date_watched)
+            .collect();
+        assert_eq!(dates, [Some("2025-03-14".to_string()), None]);
+    }
+
+    #[test]
+    fn device_login_polls_slow_down_when_asked_and_stop_once_the_code_expires() {
+        let mut model = Model::default();
+        let device_code = Secret::new("device-0");
+        model.device_code = Some(device_code.clone());
+
+        let mut got_poll = |poll, interval| {
+            let event = Event::GotDeviceLoginPoll {
+                device_code: device_code.clone(),
+                interval,
+                poll,
+            };
+            App.update(event, &mut model).events().next()
+        };
+        let next_poll = |interval| {
+            Some(Event::PollDeviceLogin {
+                device_code: device_code.clone(),
+                interval,
+            })
+        };
+
+        assert_eq!(got_poll(GitHubDeviceTokenPoll::Pending, 5), next_poll(5));
+        assert_eq!(
+            got_poll(GitHubDeviceTokenPoll::SlowDown { interval: 0 }, 5),
+            next_poll(10)
+        );
+        assert_eq!(
+            got_poll(GitHubDeviceTokenPoll::SlowDown { interval: 20 }, 10),
+            next_poll(20)
+        );
+        assert_eq!(
+            got_poll(GitHubDeviceTokenPoll::Expired, 20),
+            Some(Event::LoginFailed(LoginError::DeviceCodeExpired))
+        );
+
+        assert_eq!(model.device_code, None);
+        assert_eq!(model.device_login, None);
+    }
+
+    #[test]
+    fn tokens_from_before_accounts_are_moved_to_their_account() {
+        let now = DateTime::parse_from_rfc3339("2025-03-31T12:00:00Z")
+            .expect("valid date")
+            .with_timezone(&Utc);
+        let tokens = |access_expires_at| Tokens {
+            access_token: Token::new(
+                "bearer".into(),
+                "ghu_access".into(),
+                Some(access_expires_at),
+            ),
+            refresh_token: Some(Token::new(
+                "bearer".into(),
+                "ghr_refresh".into(),
+                Some(now + chrono::Duration::days(30)),
+            )),
+        };
+        let mut model = Model::with_clock(Arc::new(FixedClock::new(now)));
+
+        let valid = tokens(now + chrono::Duration::hours(1));
+        let mut cmd = App.update(
+            Event::GotLegacyTokensFromStore(Some(valid.clone())),
+            &mut model,
+        );
+        assert_eq!(
+            cmd.events().collect::<Vec<_>>(),
+            [Event::OnTokensLoaded {
+                tokens: valid,
+                suppress_store: false,
+            }]
+        );
+
+        let expired = tokens(now);
+        let mut cmd = App.update(Event::GotLegacyTokensFromStore(Some(expired)), &mut model);
+        let requests: Vec<_> = cmd
+            .effects()
+            .map(|effect| match effect {
+                Effect::Http(request) => request.operation.url.clone(),
+                effect => panic!("unexpected effect {effect:?}"),
+            })
+            .collect();
+        assert_eq!(requests.len(), 1);
+        assert!(
+            requests[0].contains("refresh_token=ghr_refresh"),
+            "{requests:?}"
+        );
+    }
+
+    #[test]
+    fn new_entries_are_filed_under_the_local_month() {
+        let event = || add_film(None, None);
+
+        assert_eq!(
+            filed_under("2025-03-31T12:00:00Z", TimeZone::UTC, event()),
+            (2025, 3)
+        );
+        assert_eq!(
+            filed_under("2025-03-31T12:00:00Z", TimeZone::fixed(offset(13)), event()),
+            (2025, 4)
+        );
+        assert_eq!(
+            filed_under("2025-01-01T02:00:00Z", TimeZone::fixed(offset(-5)), event()),
+            (2024, 12)
+        );
+    }
+
+    #[test]
+    fn new_entries_keep_the_month_they_were_given() {
+        let month = MonthOfYear::try_from("June").ok();
+
+        assert_eq!(
+            filed_under(
+                "2025-03-31T12:00:00Z",
+                TimeZone::UTC,
+                add_film(Some(2023), month)
+            ),
+            (2023, 6)
+        );
     }
 }
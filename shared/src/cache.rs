@@ -0,0 +1,65 @@
+use crate::film::WatchedFilm;
+use crate::markdown::ParseReport;
+use crate::settings::WatchHistorySettings;
+use crate::store::StoredValue;
+use crate::{Effect, Event};
+use chrono::{DateTime, Utc};
+use crux_core::command::RequestBuilder;
+use crux_http::http::convert::{Deserialize, Serialize};
+use std::future::Future;
+
+const WATCH_HISTORY_CACHE_STORAGE_KEY_PREFIX: &str = "watch_history_cache";
+
+fn stored_cache(login: &str) -> StoredValue<CachedWatchHistory> {
+    StoredValue::new(format!("{WATCH_HISTORY_CACHE_STORAGE_KEY_PREFIX}/{login}"))
+}
+
+/// The last watch history file fetched for an account, kept so it can be shown before the network
+/// request completes or when there is no connection.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct CachedWatchHistory {
+    /// The settings the file was fetched with, so a cache for a different repository is not shown.
+    pub settings: WatchHistorySettings,
+    pub content: String,
+    /// Sent with the next request for the file, so an unchanged file is answered from `content`.
+    pub etag: Option<String>,
+    pub fetched_at: DateTime<Utc>,
+    pub films: Vec<WatchedFilm>,
+    pub parse_report: ParseReport,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum Freshness {
+    /// Showing the cached history while it is revalidated, or because revalidation failed.
+    Stale {
+        fetched_at: DateTime<Utc>,
+    },
+    Fresh,
+}
+
+#[derive(Clone)]
+pub struct WatchHistoryCacheStore;
+
+impl WatchHistoryCacheStore {
+    pub fn get_cache(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<CachedWatchHistory>>> {
+        stored_cache(login).get()
+    }
+
+    pub fn set_cache(
+        &self,
+        login: &str,
+        cache: CachedWatchHistory,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_cache(login).set(cache)
+    }
+
+    pub fn remove_cache(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_cache(login).delete()
+    }
+}
@@ -0,0 +1,66 @@
+use chrono::{DateTime, Duration, Utc};
+use jiff::tz::TimeZone;
+use std::sync::Mutex;
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// The time zone calendar dates are read in.
+    fn time_zone(&self) -> TimeZone {
+        TimeZone::system()
+    }
+
+    /// The current calendar date in the clock's time zone, used to bucket new entries by month.
+    fn today(&self) -> jiff::civil::Date {
+        jiff::Timestamp::from_second(self.now().timestamp())
+            .expect("valid timestamp")
+            .to_zoned(self.time_zone())
+            .date()
+    }
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to, for driving expiry and date logic deterministically.
+pub struct FixedClock {
+    now: Mutex<DateTime<Utc>>,
+    time_zone: TimeZone,
+}
+
+impl FixedClock {
+    /// A clock stopped at `now`, reading dates in UTC so they don't depend on the machine.
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            now: Mutex::new(now),
+            time_zone: TimeZone::UTC,
+        }
+    }
+
+    pub fn with_time_zone(self, time_zone: TimeZone) -> Self {
+        Self { time_zone, ..self }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        *self.now.lock().expect("clock lock poisoned") = now;
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().expect("clock lock poisoned") += duration;
+    }
+}
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().expect("clock lock poisoned")
+    }
+
+    fn time_zone(&self) -> TimeZone {
+        self.time_zone.clone()
+    }
+}
@@ -0,0 +1,27 @@
+use crux_core::capability::Operation;
+use crux_core::command::RequestBuilder;
+use crux_core::{Command, Request};
+use crux_http::http::convert::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DelayOperation {
+    pub millis: u64,
+}
+
+impl Operation for DelayOperation {
+    type Output = ();
+}
+
+pub fn delay<Effect, Event>(
+    duration: Duration,
+) -> RequestBuilder<Effect, Event, impl Future<Output = ()>>
+where
+    Effect: Send + From<Request<DelayOperation>> + 'static,
+    Event: Send + 'static,
+{
+    Command::request_from_shell(DelayOperation {
+        millis: duration.as_millis() as u64,
+    })
+}
@@ -0,0 +1,119 @@
+use crate::github::GitHubApiError;
+use chrono::{DateTime, Utc};
+use crux_http::http::StatusCode;
+use crux_http::HttpError;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum AppError {
+    /// The request didn't reach GitHub, or GitHub failed to handle it.
+    Network(String),
+    Unauthorized,
+    /// The account is signed in but isn't allowed to read or write the repository.
+    Forbidden(String),
+    NotFound(String),
+    /// GitHub rejected the request itself, so sending it again won't help.
+    Validation(String),
+    RateLimited {
+        reset_at: DateTime<Utc>,
+    },
+    Conflict,
+    Parse(String),
+}
+
+impl AppError {
+    /// Whether the same request could succeed later without anything else changing.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Network(_) | Self::RateLimited { .. })
+    }
+}
+
+impl From<GitHubApiError> for AppError {
+    fn from(value: GitHubApiError) -> Self {
+        match value {
+            GitHubApiError::HttpError(HttpError::Http { code, message, .. }) => match code {
+                StatusCode::Unauthorized => Self::Unauthorized,
+                StatusCode::Forbidden => Self::Forbidden(message),
+                StatusCode::NotFound => Self::NotFound(message),
+                code if code.is_client_error() => Self::Validation(format!("{code}: {message}")),
+                _ => Self::Network(format!("{code}: {message}")),
+            },
+            GitHubApiError::HttpError(HttpError::Json(message)) => Self::Parse(message),
+            GitHubApiError::HttpError(err) => Self::Network(err.to_string()),
+            GitHubApiError::ReAuthenticationRequired => Self::Unauthorized,
+            GitHubApiError::ShaConflict => Self::Conflict,
+            GitHubApiError::InvalidContent(message) => Self::Parse(message),
+            GitHubApiError::RateLimited { reset_at } => Self::RateLimited { reset_at },
+        }
+    }
+}
+
+/// Returned across the FFI boundary instead of panicking when the bridge cannot process a
+/// message, so a malformed event or response does not take the whole shell down with it.
+#[derive(Debug)]
+pub enum CoreError {
+    Bridge(String),
+}
+
+impl Display for CoreError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bridge(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for CoreError {}
+
+impl From<CoreError> for wasm_bindgen::JsValue {
+    fn from(value: CoreError) -> Self {
+        wasm_bindgen::JsValue::from_str(&value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_status(code: StatusCode) -> AppError {
+        GitHubApiError::HttpError(HttpError::Http {
+            code,
+            message: code.canonical_reason().to_string(),
+            body: None,
+        })
+        .into()
+    }
+
+    #[test]
+    fn only_server_errors_are_retried() {
+        for code in [
+            StatusCode::BadRequest,
+            StatusCode::Forbidden,
+            StatusCode::Gone,
+            StatusCode::UnprocessableEntity,
+        ] {
+            assert!(!from_status(code).is_transient(), "{code}");
+        }
+
+        for code in [
+            StatusCode::InternalServerError,
+            StatusCode::BadGateway,
+            StatusCode::ServiceUnavailable,
+        ] {
+            assert!(from_status(code).is_transient(), "{code}");
+        }
+    }
+
+    #[test]
+    fn rejected_requests_keep_the_reason_github_gave() {
+        assert_eq!(
+            from_status(StatusCode::Forbidden),
+            AppError::Forbidden("Forbidden".to_string())
+        );
+        assert!(matches!(
+            from_status(StatusCode::UnprocessableEntity),
+            AppError::Validation(message) if message.contains("Unprocessable Entity")
+        ));
+    }
+}
@@ -0,0 +1,65 @@
+use crate::store::StoredValue;
+use crate::{Effect, Event};
+use crux_core::command::RequestBuilder;
+use crux_http::http::convert::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+
+const ETAG_CACHE_STORAGE_KEY_PREFIX: &str = "etag_cache";
+
+fn stored_responses(login: &str) -> StoredValue<HashMap<String, ETaggedResponse>> {
+    StoredValue::new(format!("{ETAG_CACHE_STORAGE_KEY_PREFIX}/{login}"))
+}
+
+/// The body of the last successful response for a URL, along with the `ETag` GitHub sent with it.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ETaggedResponse {
+    pub etag: String,
+    pub body: String,
+}
+
+/// The responses are kept per account, as the same URL answers differently for each account.
+#[derive(Clone)]
+pub struct ETagStore;
+
+impl ETagStore {
+    pub fn get_response(
+        &self,
+        login: &str,
+        url: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<ETaggedResponse>>> {
+        let url = url.to_string();
+
+        stored_responses(login)
+            .get()
+            .map(move |responses| responses?.remove(&url))
+    }
+
+    pub fn set_response(
+        &self,
+        login: &str,
+        url: &str,
+        response: ETaggedResponse,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        let stored = stored_responses(login);
+        let url = url.to_string();
+
+        RequestBuilder::new(move |ctx| async move {
+            let mut responses = stored
+                .get()
+                .into_future(ctx.clone())
+                .await
+                .unwrap_or_default();
+            responses.insert(url, response);
+
+            stored.set(responses).into_future(ctx).await;
+        })
+    }
+
+    pub fn remove_responses(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_responses(login).delete()
+    }
+}
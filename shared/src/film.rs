@@ -1,4 +1,6 @@
 use crux_http::http::convert::{Deserialize, Serialize};
+use jiff::civil::Date;
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display};
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -7,57 +9,332 @@ pub struct WatchedFilm {
     pub rating: Rating,
     pub year_watched: i16,
     pub month_of_year_watched: MonthOfYear,
+    /// `None` for entries that only record the month.
+    pub day_of_month_watched: Option<i8>,
+    /// Marked as a rewatch in the list, for films that were also seen before this entry.
+    pub rewatch: bool,
+    /// Written as `#tag`, such as where the film was watched.
+    pub tags: Vec<String>,
+    /// Who the film was watched with, written as `@name`.
+    pub companions: Vec<String>,
+    /// A review or other notes nested under the entry, as markdown.
+    pub notes: Option<String>,
 }
 
+impl WatchedFilm {
+    /// Whether this is a viewing of the title, ignoring case.
+    pub fn is_title(&self, title: &str) -> bool {
+        title_key(&self.title) == title_key(title)
+    }
+
+    /// Entries that only record the month sort before the dated entries in it.
+    fn watched_order(&self) -> (i16, i8, Option<i8>) {
+        (
+            self.year_watched,
+            self.month_of_year_watched.number(),
+            self.day_of_month_watched,
+        )
+    }
+
+    /// The full date the film was watched, for entries that record the day.
+    pub fn date_watched(&self) -> Option<Date> {
+        Date::new(
+            self.year_watched,
+            self.month_of_year_watched.number(),
+            self.day_of_month_watched?,
+        )
+        .ok()
+    }
+}
+
+/// A viewing as the shell shows it, with the date worked out by the core.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub enum Rating {
-    VeryBad,
-    Bad,
-    Meh,
-    Good,
-    VeryGood,
-    Goat,
+pub struct WatchedFilmView {
+    pub film: WatchedFilm,
+    /// [`WatchedFilm::date_watched`] as an ISO 8601 date.
+    pub date_watched: Option<String>,
 }
 
-pub enum TryFromRatingError {
-    EmptyString,
-    InvalidRating(String),
+impl From<WatchedFilm> for WatchedFilmView {
+    fn from(film: WatchedFilm) -> Self {
+        Self {
+            date_watched: film.date_watched().map(|date| date.to_string()),
+            film,
+        }
+    }
 }
 
-impl TryFrom<&str> for Rating {
-    type Error = TryFromRatingError;
+fn title_key(title: &str) -> String {
+    title.trim().to_lowercase()
+}
 
-    fn try_from(value: &str) -> Result<Self, TryFromRatingError> {
-        match value.trim().to_lowercase().as_str() {
-            "" => Err(TryFromRatingError::EmptyString),
-            "very bad" => Ok(Self::VeryBad),
-            "bad" => Ok(Self::Bad),
-            "meh" => Ok(Self::Meh),
-            "good" => Ok(Self::Good),
-            "very good" => Ok(Self::VeryGood),
-            "goat" => Ok(Self::Goat),
-            _ => Err(TryFromRatingError::InvalidRating(value.to_string())),
+/// Every viewing of a title, folded into one entry.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FilmSummary {
+    /// The title as written for the most recent viewing.
+    pub title: String,
+    pub first_watched: WatchedFilm,
+    pub last_watched: WatchedFilm,
+    pub watch_count: usize,
+    /// The rating given at each viewing, oldest first.
+    pub rating_history: Vec<Rating>,
+    /// The first viewing in the history is marked as a rewatch, so the film was seen before the
+    /// history began.
+    pub seen_before_history: bool,
+}
+
+impl FilmSummary {
+    /// Groups the films by title, ordered by title.
+    pub fn from_films(films: &[WatchedFilm]) -> Vec<Self> {
+        let mut titles: BTreeMap<String, Vec<WatchedFilm>> = BTreeMap::new();
+
+        for film in films {
+            titles
+                .entry(title_key(&film.title))
+                .or_default()
+                .push(film.clone());
+        }
+
+        titles
+            .into_values()
+            .filter_map(|viewings| Self::from_viewings(&sort_viewings(viewings)))
+            .collect()
+    }
+
+    /// Summarises the viewings of a single title, which must be oldest first.
+    pub fn from_viewings(viewings: &[WatchedFilm]) -> Option<Self> {
+        let first = viewings.first()?;
+        let last = viewings.last()?;
+
+        Some(Self {
+            title: last.title.clone(),
+            first_watched: first.clone(),
+            last_watched: last.clone(),
+            watch_count: viewings.len(),
+            rating_history: viewings.iter().map(|film| film.rating.clone()).collect(),
+            seen_before_history: first.rewatch,
+        })
+    }
+}
+
+/// Narrows the films to those with a tag or companion, ignoring case.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FilmFilter {
+    Tag(String),
+    Companion(String),
+}
+
+impl FilmFilter {
+    pub fn matches(&self, film: &WatchedFilm) -> bool {
+        match self {
+            Self::Tag(tag) => film.tags.iter().any(|x| x.eq_ignore_ascii_case(tag)),
+            Self::Companion(name) => film.companions.iter().any(|x| x.eq_ignore_ascii_case(name)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FilmGrouping {
+    Tag,
+    Companion,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct FilmGroup {
+    pub name: String,
+    pub films: Vec<WatchedFilm>,
+}
+
+impl FilmGrouping {
+    /// Groups the films by name, ignoring case. A film appears in the group for each of its tags
+    /// or companions, and films without any are left out.
+    pub fn group(&self, films: &[WatchedFilm]) -> Vec<FilmGroup> {
+        let mut groups: BTreeMap<String, FilmGroup> = BTreeMap::new();
+
+        for film in films {
+            let names = match self {
+                Self::Tag => &film.tags,
+                Self::Companion => &film.companions,
+            };
+
+            for name in names {
+                groups
+                    .entry(name.to_lowercase())
+                    .or_insert_with(|| FilmGroup {
+                        name: name.clone(),
+                        films: vec![],
+                    })
+                    .films
+                    .push(film.clone());
+            }
         }
+
+        groups.into_values().collect()
     }
 }
 
+/// Every viewing of the title, oldest first.
+pub fn viewings_of(films: &[WatchedFilm], title: &str) -> Vec<WatchedFilm> {
+    sort_viewings(
+        films
+            .iter()
+            .filter(|film| film.is_title(title))
+            .cloned()
+            .collect(),
+    )
+}
+
+fn sort_viewings(mut viewings: Vec<WatchedFilm>) -> Vec<WatchedFilm> {
+    viewings.sort_by_key(WatchedFilm::watched_order);
+    viewings
+}
+
+/// A rating given on a [`RatingScale`], kept with the label of its level.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Rating {
+    pub label: String,
+    pub value: u32,
+}
+
 impl Display for Rating {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let rating_str = match self {
-            Self::VeryBad => "very bad",
-            Self::Bad => "bad",
-            Self::Meh => "meh",
-            Self::Good => "good",
-            Self::VeryGood => "very good",
-            Self::Goat => "goat",
-        };
-        write!(f, "{}", rating_str)
+        write!(f, "{}", self.label)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RatingLevel {
+    /// How the level is written when saving.
+    pub label: String,
+    /// Other ways of writing the level that are accepted when reading, such as `★★★½`.
+    pub aliases: Vec<String>,
+    /// Orders the levels and lets ratings be compared, in whatever unit suits the scale. A scale
+    /// with half stars can count in halves.
+    pub value: u32,
+}
+
+impl RatingLevel {
+    fn new(label: &str, value: u32) -> Self {
+        Self {
+            label: label.to_string(),
+            aliases: vec![],
+            value,
+        }
+    }
+
+    pub fn rating(&self) -> Rating {
+        Rating {
+            label: self.label.clone(),
+            value: self.value,
+        }
+    }
+
+    fn is_written_as(&self, text: &str) -> bool {
+        std::iter::once(&self.label)
+            .chain(&self.aliases)
+            .any(|x| x.trim().to_lowercase() == text)
+    }
+}
+
+/// The levels ratings are given on, lowest first.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RatingScale {
+    pub levels: Vec<RatingLevel>,
+}
+
+impl Default for RatingScale {
+    fn default() -> Self {
+        Self {
+            levels: vec![
+                RatingLevel::new("very bad", 1),
+                RatingLevel::new("bad", 2),
+                RatingLevel::new("meh", 3),
+                RatingLevel::new("good", 4),
+                RatingLevel::new("very good", 5),
+                RatingLevel::new("goat", 6),
+            ],
+        }
+    }
+}
+
+pub enum TryFromRatingError {
+    EmptyString,
+    InvalidRating(String),
+}
+
+impl RatingScale {
+    /// Reads a rating written with the label or an alias of a level, ignoring case.
+    pub fn parse(&self, value: &str) -> Result<Rating, TryFromRatingError> {
+        let text = value.trim().to_lowercase();
+
+        if text.is_empty() {
+            return Err(TryFromRatingError::EmptyString);
+        }
+
+        self.levels
+            .iter()
+            .find(|level| level.is_written_as(&text))
+            .map(RatingLevel::rating)
+            .ok_or(TryFromRatingError::InvalidRating(value.to_string()))
+    }
+
+    /// A scale needs at least one level, and no two levels can share a value or be written the
+    /// same way, or ratings could not be read back as they were given.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.levels.is_empty() {
+            return Err("The rating scale has no levels".to_string());
+        }
+
+        let mut values = Vec::new();
+        let mut texts = Vec::new();
+
+        for level in &self.levels {
+            if values.contains(&level.value) {
+                return Err(format!(
+                    "More than one rating has the value {}",
+                    level.value
+                ));
+            }
+
+            values.push(level.value);
+
+            for text in std::iter::once(&level.label).chain(&level.aliases) {
+                let text = text.trim().to_lowercase();
+
+                if text.is_empty() {
+                    return Err("A rating has an empty label or alias".to_string());
+                }
+
+                if texts.contains(&text) {
+                    return Err(format!("More than one rating is written as \"{text}\""));
+                }
+
+                texts.push(text);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The label to write a rating with, which is the label of the level with the same value so
+    /// that a relabelled level is written the new way.
+    pub fn label<'a>(&'a self, rating: &'a Rating) -> &'a str {
+        self.levels
+            .iter()
+            .find(|level| level.value == rating.value)
+            .map_or(&rating.label, |level| &level.label)
     }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, Ord, PartialOrd)]
 pub struct MonthOfYear(i8);
 
+impl MonthOfYear {
+    pub fn number(&self) -> i8 {
+        self.0
+    }
+}
+
 pub enum TryFromMonthOfYearError {
     EmptyString,
     InvalidMonth(String),
@@ -132,3 +409,112 @@ impl Display for MonthOfYear {
         write!(f, "{}", month_name)
     }
 }
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum FilmChange {
+    Add(WatchedFilm),
+    Edit {
+        film: WatchedFilm,
+        title: String,
+        rating: Rating,
+        tags: Vec<String>,
+        companions: Vec<String>,
+        notes: Option<String>,
+    },
+    Delete(WatchedFilm),
+}
+
+impl FilmChange {
+    pub fn commit_message(&self) -> String {
+        match self {
+            Self::Add(film) => format!("Add {}", film.title),
+            Self::Edit { film, .. } => format!("Edit {}", film.title),
+            Self::Delete(film) => format!("Delete {}", film.title),
+        }
+    }
+
+    /// Applies the change to an already parsed list, so it can be shown before it is saved.
+    pub fn apply_to(&self, films: &mut Vec<WatchedFilm>) {
+        match self {
+            Self::Add(film) => films.push(film.clone()),
+            Self::Edit {
+                film,
+                title,
+                rating,
+                tags,
+                companions,
+                notes,
+            } => {
+                if let Some(existing) = films.iter_mut().find(|x| *x == film) {
+                    existing.title = title.clone();
+                    existing.rating = rating.clone();
+                    existing.tags = tags.clone();
+                    existing.companions = companions.clone();
+                    existing.notes = notes.clone();
+                }
+            }
+            Self::Delete(film) => {
+                if let Some(index) = films.iter().position(|x| x == film) {
+                    films.remove(index);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn viewing(title: &str, rating: &str, year: i16, month: i8, day: Option<i8>) -> WatchedFilm {
+        WatchedFilm {
+            title: title.to_string(),
+            rating: RatingScale::default()
+                .parse(rating)
+                .ok()
+                .expect("rating on the default scale"),
+            year_watched: year,
+            month_of_year_watched: MonthOfYear::try_from(month).expect("valid month"),
+            day_of_month_watched: day,
+            rewatch: false,
+            tags: vec![],
+            companions: vec![],
+            notes: None,
+        }
+    }
+
+    #[test]
+    fn summaries_fold_every_viewing_of_a_title_oldest_first() {
+        let films = [
+            viewing("Heat", "good", 2024, 5, Some(3)),
+            WatchedFilm {
+                rewatch: true,
+                ..viewing("heat", "meh", 2023, 1, None)
+            },
+            viewing("Alien", "goat", 2024, 1, None),
+            viewing("Heat", "goat", 2024, 5, None),
+        ];
+
+        let summaries = FilmSummary::from_films(&films);
+        let titles: Vec<_> = summaries.iter().map(|summary| &summary.title).collect();
+        assert_eq!(titles, ["Alien", "Heat"]);
+
+        let heat = &summaries[1];
+        assert_eq!(heat.first_watched, films[1]);
+        assert_eq!(heat.last_watched, films[0]);
+        assert_eq!(heat.watch_count, 3);
+        assert!(heat.seen_before_history);
+
+        let ratings: Vec<_> = heat
+            .rating_history
+            .iter()
+            .map(|rating| rating.label.as_str())
+            .collect();
+        assert_eq!(ratings, ["meh", "goat", "good"]);
+
+        let alien = &summaries[0];
+        assert_eq!(alien.first_watched, alien.last_watched);
+        assert_eq!(alien.watch_count, 1);
+        assert!(!alien.seen_before_history);
+    }
+}
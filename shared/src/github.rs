@@ -1,12 +1,19 @@
-use crate::tokens::{Token, TokenStore, Tokens};
+use crate::accounts::AccountStore;
+use crate::clock::Clock;
+use crate::etag::{ETagStore, ETaggedResponse};
+use crate::rate_limit::RateLimitTracker;
+use crate::tokens::{Secret, Token, TokenStore, Tokens};
 use crate::{Effect, Event};
-use chrono::{Duration, Utc};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{DateTime, Duration, Utc};
 use crux_core::command::RequestBuilder;
 use crux_http::http::convert::{Deserialize, Serialize};
+use crux_http::http::StatusCode;
 use crux_http::{Http, HttpError};
+use futures::lock::Mutex as AsyncMutex;
 use std::fmt::{Debug, Formatter};
 use std::future::Future;
-use std::sync::LazyLock;
+use std::sync::{Arc, LazyLock};
 use url::Url;
 use url_macro::url;
 
@@ -18,7 +25,9 @@ pub static GITHUB_OAUTH_AUTHORIZE_URL: LazyLock<Url> = LazyLock::new(|| url!("ht
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct GitHubConfiguration {
     pub client_id: String,
-    pub client_secret: String,
+    /// Only needed by OAuth apps that still require a secret alongside PKCE.
+    #[serde(default)]
+    pub client_secret: Option<String>,
     pub redirect_uri: String,
 }
 
@@ -27,9 +36,39 @@ struct GitHubAccessTokenResponse {
     access_token: String,
     token_type: String,
     scope: String,
-    expires_in: u64,
-    refresh_token: String,
-    refresh_token_expires_in: u64,
+    /// Left out, along with the refresh token, when the app has token expiration turned off.
+    expires_in: Option<u64>,
+    refresh_token: Option<String>,
+    refresh_token_expires_in: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GitHubDeviceCodeResponse {
+    pub device_code: Secret,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub expires_in: u64,
+    pub interval: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+#[serde(untagged)]
+enum GitHubDeviceTokenResponse {
+    Token(GitHubAccessTokenResponse),
+    Error {
+        error: String,
+        interval: Option<u64>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum GitHubDeviceTokenPoll {
+    Pending,
+    SlowDown { interval: u64 },
+    Complete(Tokens),
+    Expired,
+    Denied,
+    Failed(String),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
@@ -39,10 +78,107 @@ pub struct GitHubAuthenticatedUserResponse {
     pub avatar_url: String,
 }
 
+#[derive(Serialize)]
+struct GitHubContentsQueryParams {
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    git_ref: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct GitHubFileContentsResponse {
+    name: String,
+    path: String,
+    sha: String,
+    size: u64,
+    content: String,
+}
+
+/// A commit of `content` to the file at `path`, replacing the version with the blob `sha`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PutFileContents {
+    pub owner: String,
+    pub repo: String,
+    pub path: String,
+    pub content: String,
+    pub message: String,
+    pub sha: String,
+    /// The repository's default branch when `None`.
+    pub branch: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+struct GitHubPutFileContentsRequest {
+    message: String,
+    content: String,
+    sha: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    branch: Option<String>,
+}
+
+/// The result of a request sent with `If-None-Match`, where a `304 Not Modified` is answered from
+/// the cached body.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GitHubConditionalResponse<T> {
+    pub body: T,
+    pub etag: Option<String>,
+    /// `false` when GitHub reported the resource as unchanged since the cached response.
+    pub changed: bool,
+}
+
+impl<T> GitHubConditionalResponse<T> {
+    fn try_map<U>(
+        self,
+        f: impl FnOnce(T) -> Result<U, GitHubApiError>,
+    ) -> Result<GitHubConditionalResponse<U>, GitHubApiError> {
+        Ok(GitHubConditionalResponse {
+            body: f(self.body)?,
+            etag: self.etag,
+            changed: self.changed,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GitHubFile {
+    pub name: String,
+    pub path: String,
+    pub sha: String,
+    pub size: u64,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GitHubPutFileContentsResponse {
+    pub content: GitHubFileMetadata,
+    pub commit: GitHubCommit,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GitHubFileMetadata {
+    pub name: String,
+    pub path: String,
+    pub sha: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GitHubCommit {
+    pub sha: String,
+    pub message: String,
+}
+
 #[derive(Clone)]
 pub enum GitHubApiError {
     HttpError(HttpError),
     ReAuthenticationRequired,
+    /// The blob SHA sent with a write no longer matches the file on the branch, so the caller
+    /// should re-fetch the file and retry.
+    ShaConflict,
+    InvalidContent(String),
+    /// GitHub refused the request until `reset_at` because a primary or secondary rate limit was
+    /// hit.
+    RateLimited {
+        reset_at: DateTime<Utc>,
+    },
 }
 
 impl Debug for GitHubApiError {
@@ -67,6 +203,13 @@ impl Debug for GitHubApiError {
             GitHubApiError::ReAuthenticationRequired => {
                 write!(f, "ReAuthenticationRequired")
             }
+            GitHubApiError::ShaConflict => write!(f, "ShaConflict"),
+            GitHubApiError::InvalidContent(message) => {
+                write!(f, "InvalidContent {{ message: {message} }}")
+            }
+            GitHubApiError::RateLimited { reset_at } => {
+                write!(f, "RateLimited {{ reset_at: {reset_at} }}")
+            }
         }
     }
 }
@@ -77,28 +220,33 @@ impl From<HttpError> for GitHubApiError {
     }
 }
 
+#[derive(Clone)]
 pub struct GitHubClient {
     base_url: String,
+    etag_store: ETagStore,
+    rate_limit: RateLimitTracker,
     token_manager: GitHubTokenManager,
 }
 
 impl GitHubClient {
     pub fn new(
+        account_store: AccountStore,
         token_store: TokenStore,
+        etag_store: ETagStore,
+        clock: Arc<dyn Clock>,
         base_url: impl Into<String>,
-        client_id: impl Into<String>,
-        client_secret: impl Into<String>,
-        redirect_uri: impl Into<String>,
+        config: GitHubConfiguration,
     ) -> Self {
         Self {
             base_url: base_url.into(),
+            etag_store,
+            rate_limit: RateLimitTracker::new(clock.clone()),
             token_manager: GitHubTokenManager {
+                account_store,
                 token_store,
-                github_auth_handler: GitHubAuthenticationHandler::new(
-                    client_id,
-                    client_secret,
-                    redirect_uri,
-                ),
+                clock: clock.clone(),
+                github_auth_handler: GitHubAuthenticationHandler::new(clock, config),
+                refresh_lock: Arc::new(AsyncMutex::new(())),
             },
         }
     }
@@ -111,22 +259,280 @@ impl GitHubClient {
         )
     }
 
+    /// Sends a GET request with the `ETag` of the cached response, so an unchanged resource costs
+    /// a `304` rather than a full download against the rate limit.
+    fn get_conditional(
+        &self,
+        url: String,
+        accept: &'static str,
+        cached: Option<ETaggedResponse>,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubConditionalResponse<String>, GitHubApiError>>,
+    > {
+        let rate_limit = self.rate_limit.clone();
+
+        self.token_manager
+            .get_access_token()
+            .then_request(move |access_token| {
+                RequestBuilder::new(move |ctx| async move {
+                    let Ok(access_token) = access_token else {
+                        return Err(GitHubApiError::ReAuthenticationRequired);
+                    };
+
+                    let mut request = Http::get(url)
+                        .header(
+                            "Authorization",
+                            access_token.to_authorization_header_value(),
+                        )
+                        .header("Accept", accept);
+
+                    if let Some(cached) = &cached {
+                        request = request.header("If-None-Match", cached.etag.clone());
+                    }
+
+                    let res = rate_limit.track(
+                        &ctx,
+                        request
+                            .expect_string()
+                            .build()
+                            .into_future(ctx.clone())
+                            .await,
+                    );
+
+                    match (res, cached) {
+                        (Ok(res), _) => Ok(GitHubConditionalResponse {
+                            body: res.body().cloned().expect("valid body"),
+                            etag: res
+                                .header("ETag")
+                                .map(|values| values.last().as_str().to_string()),
+                            changed: true,
+                        }),
+                        (
+                            Err(GitHubApiError::HttpError(HttpError::Http {
+                                code: StatusCode::NotModified,
+                                ..
+                            })),
+                            Some(cached),
+                        ) => Ok(GitHubConditionalResponse {
+                            body: cached.body,
+                            etag: Some(cached.etag),
+                            changed: false,
+                        }),
+                        (Err(err), _) => Err(err),
+                    }
+                })
+            })
+    }
+
+    /// A conditional request for a resource the app doesn't cache itself, revalidated against
+    /// the last response kept for the active account in the `ETag` store.
+    fn get_conditional_from_etag_store(
+        &self,
+        url: String,
+        accept: &'static str,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubConditionalResponse<String>, GitHubApiError>>,
+    > {
+        let client = self.clone();
+
+        RequestBuilder::new(move |ctx| async move {
+            let login = client
+                .token_manager
+                .account_store
+                .get_accounts()
+                .into_future(ctx.clone())
+                .await
+                .active;
+
+            let cached = match &login {
+                Some(login) => {
+                    client
+                        .etag_store
+                        .get_response(login, &url)
+                        .into_future(ctx.clone())
+                        .await
+                }
+                None => None,
+            };
+
+            let res = client
+                .get_conditional(url.clone(), accept, cached)
+                .into_future(ctx.clone())
+                .await;
+
+            if let (
+                Some(login),
+                Ok(GitHubConditionalResponse {
+                    body,
+                    etag: Some(etag),
+                    changed: true,
+                }),
+            ) = (&login, &res)
+            {
+                let response = ETaggedResponse {
+                    etag: etag.clone(),
+                    body: body.clone(),
+                };
+                client
+                    .etag_store
+                    .set_response(login, &url, response)
+                    .into_future(ctx)
+                    .await;
+            }
+
+            res
+        })
+    }
+
     pub fn get_access_token_from_code(
         &self,
-        code: impl Into<String>,
+        code: Secret,
+        code_verifier: Secret,
     ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<Tokens, GitHubApiError>>> {
-        self.token_manager.get_access_token_from_code(code)
+        self.token_manager
+            .get_access_token_from_code(code, code_verifier)
+    }
+
+    /// Exchanges a refresh token that isn't stored against an account yet for a new pair.
+    pub fn refresh_tokens(
+        &self,
+        refresh_token: &Token,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<Tokens, GitHubApiError>>> {
+        self.token_manager
+            .github_auth_handler
+            .get_access_token_from_refresh_token(refresh_token.access_token.expose())
+    }
+
+    pub fn request_device_code(
+        &self,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubDeviceCodeResponse, GitHubApiError>>,
+    > {
+        self.token_manager.github_auth_handler.request_device_code()
+    }
+
+    pub fn poll_device_token(
+        &self,
+        device_code: Secret,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubDeviceTokenPoll, GitHubApiError>>,
+    > {
+        self.token_manager
+            .github_auth_handler
+            .poll_device_token(device_code)
     }
 
     pub fn get_authenticated_user(
         &self,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<
+            Output = Result<
+                GitHubConditionalResponse<GitHubAuthenticatedUserResponse>,
+                GitHubApiError,
+            >,
+        >,
+    > {
+        self.get_conditional_from_etag_store(self.build_url("user"), GITHUB_JSON_MEDIA_TYPE_NAME)
+            .map(|x| {
+                x.and_then(|res| {
+                    res.try_map(|body| {
+                        serde_json::from_str(&body)
+                            .map_err(|err| GitHubApiError::InvalidContent(err.to_string()))
+                    })
+                })
+            })
+    }
+
+    /// Fetches the user for an explicit token rather than the stored one, so that a token can be
+    /// checked before it is saved.
+    pub fn get_authenticated_user_with_token(
+        &self,
+        access_token: Token,
     ) -> RequestBuilder<
         Effect,
         Event,
         impl Future<Output = Result<GitHubAuthenticatedUserResponse, GitHubApiError>>,
     > {
+        let rate_limit = self.rate_limit.clone();
+
         let url = self.build_url("user");
 
+        RequestBuilder::new(move |ctx| async move {
+            let res = Http::get(url)
+                .header(
+                    "Authorization",
+                    access_token.to_authorization_header_value(),
+                )
+                .header("Accept", GITHUB_JSON_MEDIA_TYPE_NAME)
+                .expect_json::<GitHubAuthenticatedUserResponse>()
+                .build()
+                .into_future(ctx.clone())
+                .await;
+
+            rate_limit
+                .track(&ctx, res)
+                .map(|res| res.body().cloned().expect("valid body"))
+        })
+    }
+
+    /// Fetches the file, answering from `cached` if it is unchanged since it was fetched.
+    pub fn get_file_contents(
+        &self,
+        owner: impl Into<String>,
+        repo: impl Into<String>,
+        path: impl Into<String>,
+        git_ref: Option<String>,
+        cached: Option<ETaggedResponse>,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubConditionalResponse<String>, GitHubApiError>>,
+    > {
+        let mut url = self.build_url(format!(
+            "repos/{}/{}/contents/{}",
+            owner.into(),
+            repo.into(),
+            path.into()
+        ));
+
+        let query = serde_qs::to_string(&GitHubContentsQueryParams { git_ref })
+            .expect("valid query parameters");
+
+        if !query.is_empty() {
+            url = format!("{url}?{query}");
+        }
+
+        self.get_conditional(url, GITHUB_RAW_MEDIA_TYPE_NAME, cached)
+    }
+
+    pub fn get_file_contents_with_metadata(
+        &self,
+        owner: impl Into<String>,
+        repo: impl Into<String>,
+        path: impl Into<String>,
+        git_ref: Option<String>,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<GitHubFile, GitHubApiError>>>
+    {
+        let url = self.build_url(format!(
+            "repos/{}/{}/contents/{}",
+            owner.into(),
+            repo.into(),
+            path.into()
+        ));
+
+        let query_params = GitHubContentsQueryParams { git_ref };
+        let rate_limit = self.rate_limit.clone();
+
         self.token_manager
             .get_access_token()
             .then_request(|access_token| {
@@ -138,15 +544,31 @@ impl GitHubClient {
                                 access_token.to_authorization_header_value(),
                             )
                             .header("Accept", GITHUB_JSON_MEDIA_TYPE_NAME)
-                            .expect_json::<GitHubAuthenticatedUserResponse>()
+                            .query(&query_params)
+                            .expect("valid query parameters")
+                            .expect_json::<GitHubFileContentsResponse>()
                             .build()
                             .into_future(ctx.clone())
-                            .await?
+                            .await;
+
+                        let res = rate_limit
+                            .track(&ctx, res)?
                             .body()
                             .cloned()
                             .expect("valid body");
 
-                        Ok(res)
+                        let content = BASE64_STANDARD
+                            .decode(res.content.replace('\n', ""))
+                            .map_err(|err| GitHubApiError::InvalidContent(err.to_string()))?;
+
+                        Ok(GitHubFile {
+                            name: res.name,
+                            path: res.path,
+                            sha: res.sha,
+                            size: res.size,
+                            content: String::from_utf8(content)
+                                .map_err(|err| GitHubApiError::InvalidContent(err.to_string()))?,
+                        })
                     } else {
                         Err(GitHubApiError::ReAuthenticationRequired)
                     }
@@ -154,34 +576,54 @@ impl GitHubClient {
             })
     }
 
-    pub fn get_file_contents(
+    pub fn put_file_contents(
         &self,
-        owner: impl Into<String>,
-        repo: impl Into<String>,
-        path: impl Into<String>,
-    ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<String, GitHubApiError>>> {
+        put: PutFileContents,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubPutFileContentsResponse, GitHubApiError>>,
+    > {
         let url = self.build_url(format!(
             "repos/{}/{}/contents/{}",
-            owner.into(),
-            repo.into(),
-            path.into()
+            put.owner, put.repo, put.path
         ));
 
+        let body = GitHubPutFileContentsRequest {
+            message: put.message,
+            content: BASE64_STANDARD.encode(put.content),
+            sha: put.sha,
+            branch: put.branch,
+        };
+        let rate_limit = self.rate_limit.clone();
+
         self.token_manager
             .get_access_token()
             .then_request(|access_token| {
                 RequestBuilder::new(|ctx| async move {
                     if let Ok(access_token) = access_token {
-                        let res = Http::get(url)
+                        let res = Http::put(url)
                             .header(
                                 "Authorization",
                                 access_token.to_authorization_header_value(),
                             )
-                            .header("Accept", GITHUB_RAW_MEDIA_TYPE_NAME)
-                            .expect_string()
+                            .header("Accept", GITHUB_JSON_MEDIA_TYPE_NAME)
+                            .body_json(&body)
+                            .expect("valid request body")
+                            .expect_json::<GitHubPutFileContentsResponse>()
                             .build()
                             .into_future(ctx.clone())
-                            .await?
+                            .await;
+
+                        let res = rate_limit
+                            .track(&ctx, res)
+                            .map_err(|err| match err {
+                                GitHubApiError::HttpError(HttpError::Http {
+                                    code: StatusCode::Conflict | StatusCode::UnprocessableEntity,
+                                    ..
+                                }) => GitHubApiError::ShaConflict,
+                                err => err,
+                            })?
                             .body()
                             .cloned()
                             .expect("valid body");
@@ -197,46 +639,137 @@ impl GitHubClient {
 
 #[derive(Clone)]
 pub struct GitHubAuthenticationHandler {
+    clock: Arc<dyn Clock>,
     client_id: String,
-    client_secret: String,
+    client_secret: Option<String>,
     redirect_uri: String,
 }
 
 impl GitHubAuthenticationHandler {
-    fn new(
-        client_id: impl Into<String>,
-        client_secret: impl Into<String>,
-        redirect_uri: impl Into<String>,
-    ) -> Self {
+    fn new(clock: Arc<dyn Clock>, config: GitHubConfiguration) -> Self {
         Self {
-            client_id: client_id.into(),
-            client_secret: client_secret.into(),
-            redirect_uri: redirect_uri.into(),
+            clock,
+            client_id: config.client_id,
+            client_secret: config.client_secret,
+            redirect_uri: config.redirect_uri,
         }
     }
 
     pub fn get_access_token_from_code(
         &self,
-        code: impl Into<String>,
+        code: Secret,
+        code_verifier: Secret,
     ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<Tokens, GitHubApiError>>> {
         #[derive(Serialize)]
         struct QueryParams {
             client_id: String,
-            client_secret: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            client_secret: Option<String>,
             redirect_uri: String,
-            code: String,
+            code: Secret,
+            code_verifier: Secret,
         }
 
         let query_params = QueryParams {
             client_id: self.client_id.clone(),
             client_secret: self.client_secret.clone(),
-            code: code.into(),
+            code,
+            code_verifier,
             redirect_uri: self.redirect_uri.clone(),
         };
 
         self.get_access_token(query_params)
     }
 
+    fn request_device_code(
+        &self,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubDeviceCodeResponse, GitHubApiError>>,
+    > {
+        #[derive(Serialize)]
+        struct QueryParams {
+            client_id: String,
+        }
+
+        let query_params = QueryParams {
+            client_id: self.client_id.clone(),
+        };
+
+        Http::post(url!("https://github.com/login/device/code"))
+            .header("Accept", GITHUB_JSON_MEDIA_TYPE_NAME)
+            .query(&query_params)
+            .expect("valid query parameters")
+            .expect_json::<GitHubDeviceCodeResponse>()
+            .build()
+            .map(|x| {
+                x.map_or_else(
+                    |err| Err(GitHubApiError::HttpError(err)),
+                    |res| Ok(res.body().cloned().expect("valid body")),
+                )
+            })
+    }
+
+    fn poll_device_token(
+        &self,
+        device_code: Secret,
+    ) -> RequestBuilder<
+        Effect,
+        Event,
+        impl Future<Output = Result<GitHubDeviceTokenPoll, GitHubApiError>>,
+    > {
+        #[derive(Serialize)]
+        struct QueryParams {
+            client_id: String,
+            device_code: Secret,
+            grant_type: String,
+        }
+
+        let query_params = QueryParams {
+            client_id: self.client_id.clone(),
+            device_code,
+            grant_type: "urn:ietf:params:oauth:grant-type:device_code".into(),
+        };
+
+        let url = url!("https://github.com/login/oauth/access_token");
+        let clock = self.clock.clone();
+
+        // GitHub reports the progress of a device authorization as a 200 response with an
+        // `error` field rather than through the status code.
+        Http::post(url)
+            .header("Accept", GITHUB_JSON_MEDIA_TYPE_NAME)
+            .query(&query_params)
+            .expect("valid query parameters")
+            .expect_json::<GitHubDeviceTokenResponse>()
+            .build()
+            .map(move |x| {
+                x.map_or_else(
+                    |err| Err(GitHubApiError::HttpError(err)),
+                    |res| {
+                        let poll = match res.body().cloned().expect("valid body") {
+                            GitHubDeviceTokenResponse::Token(token) => {
+                                GitHubDeviceTokenPoll::Complete(token.into_tokens(clock.now()))
+                            }
+                            GitHubDeviceTokenResponse::Error { error, interval } => {
+                                match error.as_str() {
+                                    "authorization_pending" => GitHubDeviceTokenPoll::Pending,
+                                    "slow_down" => GitHubDeviceTokenPoll::SlowDown {
+                                        interval: interval.unwrap_or_default(),
+                                    },
+                                    "expired_token" => GitHubDeviceTokenPoll::Expired,
+                                    "access_denied" => GitHubDeviceTokenPoll::Denied,
+                                    _ => GitHubDeviceTokenPoll::Failed(error),
+                                }
+                            }
+                        };
+
+                        Ok(poll)
+                    },
+                )
+            })
+    }
+
     fn get_access_token_from_refresh_token(
         &self,
         refresh_token: impl Into<String>,
@@ -244,7 +777,8 @@ impl GitHubAuthenticationHandler {
         #[derive(Serialize)]
         struct QueryParams {
             client_id: String,
-            client_secret: String,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            client_secret: Option<String>,
             grant_type: String,
             refresh_token: String,
         }
@@ -264,6 +798,7 @@ impl GitHubAuthenticationHandler {
         query_params: Query,
     ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<Tokens, GitHubApiError>>> {
         let url = url!("https://github.com/login/oauth/access_token");
+        let clock = self.clock.clone();
 
         Http::post(url)
             .header("Accept", GITHUB_JSON_MEDIA_TYPE_NAME)
@@ -271,38 +806,51 @@ impl GitHubAuthenticationHandler {
             .expect("valid query parameters")
             .expect_json::<GitHubAccessTokenResponse>()
             .build()
-            .map(|x| {
+            .map(move |x| {
                 x.map_or_else(
                     |err| Err(GitHubApiError::HttpError(err)),
-                    |res| Ok(res.body().cloned().expect("valid body").into()),
+                    |res| {
+                        Ok(res
+                            .body()
+                            .cloned()
+                            .expect("valid body")
+                            .into_tokens(clock.now()))
+                    },
                 )
             })
     }
 }
 
-impl From<GitHubAccessTokenResponse> for Tokens {
-    fn from(value: GitHubAccessTokenResponse) -> Self {
-        let now = Utc::now();
-        Self {
+impl GitHubAccessTokenResponse {
+    fn into_tokens(self, now: DateTime<Utc>) -> Tokens {
+        let expires_at = |seconds: u64| now + Duration::seconds(seconds as i64);
+
+        Tokens {
             access_token: Token::new(
-                value.token_type.clone(),
-                value.access_token,
-                // now + Duration::seconds(value.expires_in as i64),
-                now + Duration::seconds(2),
-            ),
-            refresh_token: Token::new(
-                value.token_type.clone(),
-                value.refresh_token,
-                now + Duration::seconds(value.refresh_token_expires_in as i64),
+                self.token_type.clone(),
+                self.access_token,
+                self.expires_in.map(expires_at),
             ),
+            refresh_token: self.refresh_token.map(|refresh_token| {
+                Token::new(
+                    self.token_type.clone(),
+                    refresh_token,
+                    self.refresh_token_expires_in.map(expires_at),
+                )
+            }),
         }
     }
 }
 
 #[derive(Clone)]
 struct GitHubTokenManager {
+    account_store: AccountStore,
     token_store: TokenStore,
+    clock: Arc<dyn Clock>,
     github_auth_handler: GitHubAuthenticationHandler,
+    /// Held from reading the stored tokens until any refresh of them has been stored, so
+    /// concurrent requests never spend the same refresh token twice.
+    refresh_lock: Arc<AsyncMutex<()>>,
 }
 
 impl GitHubTokenManager {
@@ -311,39 +859,468 @@ impl GitHubTokenManager {
     ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<Token, GitHubApiError>>> {
         let github_client = self.github_auth_handler.clone();
         let token_store = self.token_store.clone();
-        token_store.get_tokens().then_request(|tokens| {
-            RequestBuilder::new(|ctx| async move {
-                if let Some(tokens) = tokens {
-                    token_store
-                        .set_tokens(tokens.clone())
+        let clock = self.clock.clone();
+        let refresh_lock = self.refresh_lock.clone();
+        self.account_store
+            .get_accounts()
+            .then_request(move |accounts| {
+                RequestBuilder::new(move |ctx| async move {
+                    let Some(login) = accounts.active else {
+                        return Err(GitHubApiError::ReAuthenticationRequired);
+                    };
+
+                    // A request that waited here while another refreshed reads the new pair.
+                    let _refreshing = refresh_lock.lock().await;
+
+                    let tokens = token_store
+                        .get_tokens(&login)
                         .into_future(ctx.clone())
                         .await;
+                    let now = clock.now();
 
-                    if tokens.access_token.is_valid() {
-                        Ok(tokens.access_token.clone())
-                    } else if tokens.refresh_token.is_valid() {
-                        github_client
-                            .get_access_token_from_refresh_token(
-                                tokens.refresh_token.access_token.clone(),
-                            )
-                            .map(|tokens| tokens.map(|tokens| tokens.access_token))
-                            .into_future(ctx.clone())
-                            .await
-                            .clone()
+                    if let Some(tokens) = tokens {
+                        if tokens.access_token.is_valid_at(now) {
+                            Ok(tokens.access_token.clone())
+                        } else if let Some(refresh_token) = tokens
+                            .refresh_token
+                            .filter(|refresh_token| refresh_token.is_valid_at(now))
+                        {
+                            let tokens = github_client
+                                .get_access_token_from_refresh_token(
+                                    refresh_token.access_token.expose(),
+                                )
+                                .into_future(ctx.clone())
+                                .await?;
+
+                            // GitHub rotates the refresh token on every exchange, so the new pair
+                            // has to be stored before it is used or the next refresh will be
+                            // rejected.
+                            token_store
+                                .set_tokens(&login, tokens.clone())
+                                .into_future(ctx.clone())
+                                .await;
+
+                            Ok(tokens.access_token)
+                        } else {
+                            Err(GitHubApiError::ReAuthenticationRequired)
+                        }
                     } else {
                         Err(GitHubApiError::ReAuthenticationRequired)
                     }
-                } else {
-                    Err(GitHubApiError::ReAuthenticationRequired)
-                }
+                })
             })
-        })
     }
 
     fn get_access_token_from_code(
         &self,
-        code: impl Into<String>,
+        code: Secret,
+        code_verifier: Secret,
     ) -> RequestBuilder<Effect, Event, impl Future<Output = Result<Tokens, GitHubApiError>>> {
-        self.github_auth_handler.get_access_token_from_code(code)
+        self.github_auth_handler
+            .get_access_token_from_code(code, code_verifier)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::Accounts;
+    use crate::clock::FixedClock;
+    use crux_core::Command;
+    use crux_http::protocol::{HttpRequest, HttpResponse, HttpResult};
+    use crux_kv::value::Value;
+    use crux_kv::{KeyValueOperation, KeyValueResponse, KeyValueResult};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const LOGIN: &str = "octocat";
+    const ACCESS_TOKEN_LIFETIME: Duration = Duration::hours(8);
+    const REFRESH_TOKEN_LIFETIME: Duration = Duration::days(184);
+
+    type Results = Arc<Mutex<Vec<Result<String, String>>>>;
+
+    /// Stands in for the shell: a key-value store, and a GitHub that accepts each refresh token
+    /// once and rotates it, as the real one does, and serves `file` with its `ETag`.
+    #[derive(Default)]
+    struct Shell {
+        store: HashMap<String, Vec<u8>>,
+        refresh_token: String,
+        refreshes: u32,
+        file: Option<ETaggedResponse>,
+        /// The `If-None-Match` header sent with each request for the file.
+        if_none_match: Vec<Option<String>>,
+    }
+
+    impl Shell {
+        /// Drives the command until it has nothing left to do, answering every request it makes.
+        fn run(&mut self, mut cmd: Command<Effect, Event>) {
+            loop {
+                let effects: Vec<_> = cmd.effects().collect();
+
+                if effects.is_empty() {
+                    break;
+                }
+
+                for effect in effects {
+                    match effect {
+                        Effect::KeyValue(mut request) => {
+                            let response = self.key_value(&request.operation);
+                            request
+                                .resolve(KeyValueResult::Ok { response })
+                                .expect("request resolves");
+                        }
+                        Effect::Http(mut request) => {
+                            let response = self.github(&request.operation);
+                            request
+                                .resolve(HttpResult::Ok(response))
+                                .expect("request resolves");
+                        }
+                        effect => panic!("unexpected effect {effect:?}"),
+                    }
+                }
+            }
+
+            assert!(cmd.is_done());
+        }
+
+        fn key_value(&mut self, operation: &KeyValueOperation) -> KeyValueResponse {
+            fn value(bytes: Option<Vec<u8>>) -> Value {
+                bytes.map_or(Value::None, Value::Bytes)
+            }
+
+            match operation {
+                KeyValueOperation::Get { key } => KeyValueResponse::Get {
+                    value: value(self.store.get(key).cloned()),
+                },
+                KeyValueOperation::Set { key, value: bytes } => KeyValueResponse::Set {
+                    previous: value(self.store.insert(key.clone(), bytes.clone())),
+                },
+                KeyValueOperation::Delete { key } => KeyValueResponse::Delete {
+                    previous: value(self.store.remove(key)),
+                },
+                operation => panic!("unexpected key-value operation {operation:?}"),
+            }
+        }
+
+        fn github(&mut self, request: &HttpRequest) -> HttpResponse {
+            let url = Url::parse(&request.url).expect("valid url");
+
+            if url.path().starts_with("/repos/") {
+                return self.file(request);
+            }
+
+            let refresh_token = url
+                .query_pairs()
+                .find(|(name, _)| name == "refresh_token")
+                .map(|(_, value)| value.into_owned());
+
+            assert_eq!(url.path(), "/login/oauth/access_token");
+
+            if refresh_token.as_ref() != Some(&self.refresh_token) {
+                return HttpResponse::status(400).build();
+            }
+
+            self.refreshes += 1;
+            self.refresh_token = format!("refresh-{}", self.refreshes);
+
+            HttpResponse::ok()
+                .json(token_response(self.refreshes, &self.refresh_token))
+                .build()
+        }
+
+        fn file(&mut self, request: &HttpRequest) -> HttpResponse {
+            let file = self.file.clone().expect("a file to serve");
+            let if_none_match = request
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("If-None-Match"))
+                .map(|header| header.value.clone());
+
+            self.if_none_match.push(if_none_match.clone());
+
+            if if_none_match.as_ref() == Some(&file.etag) {
+                return HttpResponse::status(304).build();
+            }
+
+            HttpResponse::ok()
+                .header("ETag", &file.etag)
+                .body(file.body)
+                .build()
+        }
+    }
+
+    fn token_response(n: u32, refresh_token: &str) -> GitHubAccessTokenResponse {
+        GitHubAccessTokenResponse {
+            access_token: format!("access-{n}"),
+            token_type: "bearer".to_string(),
+            scope: String::new(),
+            expires_in: Some(ACCESS_TOKEN_LIFETIME.num_seconds() as u64),
+            refresh_token: Some(refresh_token.to_string()),
+            refresh_token_expires_in: Some(REFRESH_TOKEN_LIFETIME.num_seconds() as u64),
+        }
+    }
+
+    fn configuration() -> GitHubConfiguration {
+        GitHubConfiguration {
+            client_id: "client-id".to_string(),
+            client_secret: None,
+            redirect_uri: "watch-history://login".to_string(),
+        }
+    }
+
+    /// A client for a signed-in account holding `access-0` and `refresh-0`, issued now.
+    fn signed_in_client(clock: Arc<FixedClock>) -> (GitHubClient, Shell) {
+        let client = GitHubClient::new(
+            AccountStore,
+            TokenStore,
+            ETagStore,
+            clock.clone(),
+            "https://api.github.com",
+            configuration(),
+        );
+
+        let mut accounts = Accounts::default();
+        accounts.add(LOGIN);
+        let tokens = token_response(0, "refresh-0").into_tokens(clock.now());
+
+        let mut shell = Shell {
+            refresh_token: "refresh-0".to_string(),
+            ..Shell::default()
+        };
+        shell.run(Command::new(|ctx| async move {
+            AccountStore
+                .set_accounts(accounts)
+                .into_future(ctx.clone())
+                .await;
+            TokenStore.set_tokens(LOGIN, tokens).into_future(ctx).await;
+        }));
+
+        (client, shell)
+    }
+
+    fn signed_in(clock: Arc<FixedClock>) -> (GitHubTokenManager, Shell) {
+        let (client, shell) = signed_in_client(clock);
+
+        (client.token_manager, shell)
+    }
+
+    fn get_access_token(manager: &GitHubTokenManager, results: &Results) -> Command<Effect, Event> {
+        let request = manager.get_access_token();
+        let results = results.clone();
+
+        Command::new(|ctx| async move {
+            let result = request.into_future(ctx).await;
+
+            results.lock().unwrap().push(
+                result
+                    .map(|token| token.access_token.expose().to_string())
+                    .map_err(|err| format!("{err:?}")),
+            );
+        })
+    }
+
+    /// Two requests fired together, like the watch history and change queue after sign-in.
+    fn get_access_token_twice(
+        manager: &GitHubTokenManager,
+        shell: &mut Shell,
+    ) -> Vec<Result<String, String>> {
+        let results = Results::default();
+
+        shell.run(get_access_token(manager, &results).and(get_access_token(manager, &results)));
+
+        Arc::into_inner(results)
+            .expect("requests finished")
+            .into_inner()
+            .unwrap()
+    }
+
+    fn start() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-01-01T09:00:00Z")
+            .expect("valid date")
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn concurrent_requests_share_one_refresh_per_expiry() {
+        let clock = Arc::new(FixedClock::new(start()));
+        let (manager, mut shell) = signed_in(clock.clone());
+
+        for cycle in 1..=3 {
+            let current = Ok(format!("access-{}", cycle - 1));
+
+            clock.advance(ACCESS_TOKEN_LIFETIME - Duration::minutes(1));
+            assert_eq!(
+                get_access_token_twice(&manager, &mut shell),
+                [current.clone(), current]
+            );
+            assert_eq!(shell.refreshes, cycle - 1);
+
+            let refreshed = Ok(format!("access-{cycle}"));
+
+            clock.advance(Duration::minutes(1));
+            assert_eq!(
+                get_access_token_twice(&manager, &mut shell),
+                [refreshed.clone(), refreshed]
+            );
+            assert_eq!(shell.refreshes, cycle);
+        }
+    }
+
+    #[test]
+    fn requires_sign_in_once_the_refresh_token_expires() {
+        let clock = Arc::new(FixedClock::new(start()));
+        let (manager, mut shell) = signed_in(clock.clone());
+        let reauthenticate = Err("ReAuthenticationRequired".to_string());
+
+        clock.advance(REFRESH_TOKEN_LIFETIME);
+        assert_eq!(
+            get_access_token_twice(&manager, &mut shell),
+            [reauthenticate.clone(), reauthenticate]
+        );
+        assert_eq!(shell.refreshes, 0);
+    }
+
+    /// Polls for the device's tokens, with GitHub answering `body`.
+    fn poll_device_token(body: serde_json::Value) -> GitHubDeviceTokenPoll {
+        let handler =
+            GitHubAuthenticationHandler::new(Arc::new(FixedClock::new(start())), configuration());
+        let request = handler.poll_device_token(Secret::new("device-0"));
+        let poll = Arc::new(Mutex::new(None));
+
+        let mut cmd: Command<Effect, Event> = Command::new({
+            let poll = poll.clone();
+            |ctx| async move {
+                *poll.lock().unwrap() = Some(request.into_future(ctx).await);
+            }
+        });
+
+        match cmd.effects().next() {
+            Some(Effect::Http(mut request)) => {
+                let url = Url::parse(&request.operation.url).expect("valid url");
+                assert!(url
+                    .query_pairs()
+                    .any(|(name, value)| name == "device_code" && value == "device-0"));
+
+                request
+                    .resolve(HttpResult::Ok(HttpResponse::ok().json(body).build()))
+                    .expect("request resolves");
+            }
+            effect => panic!("unexpected effect {effect:?}"),
+        }
+
+        assert!(cmd.effects().next().is_none());
+        assert!(cmd.is_done());
+
+        let poll = poll.lock().unwrap().take();
+        poll.expect("poll finished").expect("poll succeeds")
+    }
+
+    #[test]
+    fn device_token_polls_report_progress() {
+        let error = |error: &str| serde_json::json!({ "error": error });
+
+        assert_eq!(
+            poll_device_token(error("authorization_pending")),
+            GitHubDeviceTokenPoll::Pending
+        );
+        assert_eq!(
+            poll_device_token(serde_json::json!({ "error": "slow_down", "interval": 10 })),
+            GitHubDeviceTokenPoll::SlowDown { interval: 10 }
+        );
+        assert_eq!(
+            poll_device_token(error("expired_token")),
+            GitHubDeviceTokenPoll::Expired
+        );
+    }
+
+    #[test]
+    fn device_tokens_that_do_not_expire_complete_the_poll() {
+        let poll = poll_device_token(serde_json::json!({
+            "access_token": "ghu_access",
+            "token_type": "bearer",
+            "scope": "",
+        }));
+
+        assert_eq!(
+            poll,
+            GitHubDeviceTokenPoll::Complete(Tokens {
+                access_token: Token::new("bearer".to_string(), "ghu_access".to_string(), None),
+                refresh_token: None,
+            })
+        );
+
+        let poll = poll_device_token(serde_json::to_value(token_response(0, "refresh-0")).unwrap());
+        let GitHubDeviceTokenPoll::Complete(tokens) = poll else {
+            panic!("unexpected poll {poll:?}");
+        };
+        assert_eq!(tokens, token_response(0, "refresh-0").into_tokens(start()));
+    }
+
+    fn get_file_contents(
+        client: &GitHubClient,
+        shell: &mut Shell,
+        cached: Option<ETaggedResponse>,
+    ) -> GitHubConditionalResponse<String> {
+        let request = client.get_file_contents(LOGIN, "notes", "watch_history.md", None, cached);
+        let response = Arc::new(Mutex::new(None));
+
+        shell.run(Command::new({
+            let response = response.clone();
+            |ctx| async move {
+                *response.lock().unwrap() = Some(request.into_future(ctx).await);
+            }
+        }));
+
+        let response = response.lock().unwrap().take();
+        response
+            .expect("request finished")
+            .expect("request succeeds")
+    }
+
+    fn file(etag: &str, body: &str) -> ETaggedResponse {
+        ETaggedResponse {
+            etag: etag.to_string(),
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn revalidating_sends_the_etag_of_the_cached_response() {
+        let (client, mut shell) = signed_in_client(Arc::new(FixedClock::new(start())));
+        shell.file = Some(file("\"v2\"", "- Heat - good\n"));
+
+        let response = get_file_contents(&client, &mut shell, None);
+        assert_eq!(
+            response,
+            GitHubConditionalResponse {
+                body: "- Heat - good\n".to_string(),
+                etag: Some("\"v2\"".to_string()),
+                changed: true,
+            }
+        );
+
+        let cached = file("\"v1\"", "- Alien - goat\n");
+        let response = get_file_contents(&client, &mut shell, Some(cached));
+        assert!(response.changed);
+        assert_eq!(shell.if_none_match, [None, Some("\"v1\"".to_string())]);
+    }
+
+    #[test]
+    fn an_unchanged_resource_is_answered_from_the_cached_response() {
+        let (client, mut shell) = signed_in_client(Arc::new(FixedClock::new(start())));
+        shell.file = Some(file("\"v1\"", "- Heat - good\n"));
+
+        let cached = file("\"v1\"", "- Heat - good\n- Alien - goat\n");
+        let response = get_file_contents(&client, &mut shell, Some(cached));
+
+        assert_eq!(
+            response,
+            GitHubConditionalResponse {
+                body: "- Heat - good\n- Alien - goat\n".to_string(),
+                etag: Some("\"v1\"".to_string()),
+                changed: false,
+            }
+        );
+        assert_eq!(shell.if_none_match, [Some("\"v1\"".to_string())]);
     }
 }
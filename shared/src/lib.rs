@@ -5,13 +5,24 @@
 extern crate log;
 
 pub mod app;
+pub mod clock;
 pub mod film;
 mod github;
+mod login;
 mod redirect;
+mod delay;
+mod accounts;
 mod tokens;
+mod etag;
+mod rate_limit;
 mod config;
+mod error;
 mod services;
 mod markdown;
+mod cache;
+mod queue;
+mod settings;
+mod store;
 
 use std::sync::LazyLock;
 
@@ -21,6 +32,7 @@ pub use crux_core::{Core, ResolveError};
 pub use crux_http as http;
 
 pub use app::*;
+pub use error::CoreError;
 
 #[cfg(not(target_family = "wasm"))]
 uniffi::include_scaffolding!("shared");
@@ -28,40 +40,37 @@ uniffi::include_scaffolding!("shared");
 static CORE: LazyLock<Bridge<App>> = LazyLock::new(|| Bridge::new(Core::new()));
 
 /// Ask the core to process an event
-/// # Panics
+/// # Errors
 /// If the core fails to process the event
 #[cfg_attr(target_family = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
-#[must_use]
-pub fn process_event(data: &[u8]) -> Vec<u8> {
+pub fn process_event(data: &[u8]) -> Result<Vec<u8>, CoreError> {
     let mut effects = Vec::new();
     match CORE.update(data, &mut effects) {
-        Ok(()) => effects,
-        Err(e) => panic!("{e}"),
+        Ok(()) => Ok(effects),
+        Err(e) => Err(CoreError::Bridge(e.to_string())),
     }
 }
 
 /// Ask the core to handle a response
-/// # Panics
+/// # Errors
 /// If the core fails to handle the response
 #[cfg_attr(target_family = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
-#[must_use]
-pub fn handle_response(id: u32, data: &[u8]) -> Vec<u8> {
+pub fn handle_response(id: u32, data: &[u8]) -> Result<Vec<u8>, CoreError> {
     let mut effects = Vec::new();
     match CORE.resolve(EffectId(id), data, &mut effects) {
-        Ok(()) => effects,
-        Err(e) => panic!("{e}"),
+        Ok(()) => Ok(effects),
+        Err(e) => Err(CoreError::Bridge(e.to_string())),
     }
 }
 
 /// Ask the core to render the view
-/// # Panics
+/// # Errors
 /// If the view cannot be serialized
 #[cfg_attr(target_family = "wasm", wasm_bindgen::prelude::wasm_bindgen)]
-#[must_use]
-pub fn view() -> Vec<u8> {
+pub fn view() -> Result<Vec<u8>, CoreError> {
     let mut view_model = Vec::new();
     match CORE.view(&mut view_model) {
-        Ok(()) => view_model,
-        Err(e) => panic!("{e}"),
+        Ok(()) => Ok(view_model),
+        Err(e) => Err(CoreError::Bridge(e.to_string())),
     }
 }
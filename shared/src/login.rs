@@ -0,0 +1,151 @@
+use crate::error::AppError;
+use crate::store::StoredValue;
+use crate::tokens::Secret;
+use crate::{Effect, Event};
+use base64::prelude::{Engine, BASE64_URL_SAFE_NO_PAD};
+use chrono::{DateTime, Duration, Utc};
+use crux_core::command::RequestBuilder;
+use crux_http::http::convert::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::future::Future;
+
+const PENDING_LOGIN_STORAGE_KEY: &str = "pending_login";
+const PENDING_LOGIN_MAX_AGE_MINUTES: i64 = 10;
+
+fn stored_pending_login() -> StoredValue<PendingLogin> {
+    StoredValue::new(PENDING_LOGIN_STORAGE_KEY)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PendingLogin {
+    pub state: String,
+    pub code_verifier: Secret,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PendingLogin {
+    pub fn new(state: String, code_verifier: Secret, created_at: DateTime<Utc>) -> Self {
+        Self {
+            state,
+            code_verifier,
+            created_at,
+        }
+    }
+
+    /// The S256 PKCE challenge sent with the authorize request in place of the verifier.
+    pub fn code_challenge(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.expose()))
+    }
+
+    /// Checks that a callback belongs to this login attempt and arrived before it went stale.
+    pub fn verify(&self, state: Option<&str>, now: DateTime<Utc>) -> Result<(), LoginError> {
+        match state {
+            None => Err(LoginError::MissingState),
+            Some(state) if state != self.state => Err(LoginError::StateMismatch),
+            Some(_) if now - self.created_at > Duration::minutes(PENDING_LOGIN_MAX_AGE_MINUTES) => {
+                Err(LoginError::Expired)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub enum LoginMode {
+    #[default]
+    Redirect,
+    DeviceFlow,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DeviceLogin {
+    pub user_code: String,
+    pub verification_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum LoginError {
+    InvalidCallback,
+    Denied(String),
+    DeviceCodeExpired,
+    InvalidToken,
+    /// GitHub couldn't be asked to check the credentials, so submitting them again may work.
+    RequestFailed(AppError),
+    NoPendingLogin,
+    MissingState,
+    StateMismatch,
+    Expired,
+}
+
+#[derive(Clone)]
+pub struct PendingLoginStore;
+
+impl PendingLoginStore {
+    pub fn get_pending_login(
+        &self,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<PendingLogin>>> {
+        stored_pending_login().get()
+    }
+
+    pub fn set_pending_login(
+        &self,
+        pending_login: PendingLogin,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_pending_login().set(pending_login)
+    }
+
+    pub fn remove_pending_login(&self) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_pending_login().delete()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-03-31T12:00:00Z")
+            .expect("valid date")
+            .with_timezone(&Utc)
+    }
+
+    fn pending_login() -> PendingLogin {
+        PendingLogin::new("state-0".to_string(), Secret::new("verifier"), now())
+    }
+
+    #[test]
+    fn the_code_challenge_is_the_s256_hash_of_the_verifier() {
+        // The example in RFC 7636, Appendix B.
+        let pending_login = PendingLogin::new(
+            "state-0".to_string(),
+            Secret::new("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            now(),
+        );
+
+        assert_eq!(
+            pending_login.code_challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn callbacks_for_another_login_attempt_are_rejected() {
+        assert_eq!(
+            pending_login().verify(Some("state-1"), now()),
+            Err(LoginError::StateMismatch)
+        );
+        assert_eq!(
+            pending_login().verify(None, now()),
+            Err(LoginError::MissingState)
+        );
+    }
+
+    #[test]
+    fn callbacks_for_this_login_attempt_are_accepted_until_it_goes_stale() {
+        assert_eq!(pending_login().verify(Some("state-0"), now()), Ok(()));
+        assert_eq!(
+            pending_login().verify(Some("state-0"), now() + Duration::minutes(11)),
+            Err(LoginError::Expired)
+        );
+    }
+}
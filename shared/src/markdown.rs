@@ -1,11 +1,75 @@
-use crate::film::{MonthOfYear, Rating, WatchedFilm};
-use comrak::nodes::{AstNode, NodeHeading, NodeValue};
-use comrak::{format_commonmark, parse_document, Arena, Options};
+use crate::film::{
+    FilmChange, MonthOfYear, Rating, RatingScale, TryFromMonthOfYearError, TryFromRatingError,
+    WatchedFilm,
+};
+use comrak::nodes::{AstNode, LineColumn, NodeHeading, NodeValue, Sourcepos};
+use comrak::{parse_document, Arena, Options};
+use jiff::civil::Date;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
 use std::str::FromStr;
 
+const REWATCH_MARKER: &str = "(rewatch)";
+
 struct Film {
     title: String,
     rating: Rating,
+    day: Option<FilmDay>,
+    rewatch: bool,
+    tags: Vec<String>,
+    companions: Vec<String>,
+    notes: Option<String>,
+}
+
+impl Film {
+    /// Whether this is the item for `film`. Ratings are compared by value, so an item written with
+    /// an alias or a level's old label is still found.
+    fn is(&self, film: &WatchedFilm) -> bool {
+        self.title == film.title
+            && self.rating.value == film.rating.value
+            && self.day.as_ref().map(FilmDay::day) == film.day_of_month_watched
+            && self.rewatch == film.rewatch
+    }
+
+    /// Drops a day that isn't in the month the entry is filed under, as parsing does, so the entry
+    /// is kept without it.
+    fn check_day(&mut self, year: i16, month: &MonthOfYear) -> Result<(), ParseWarningReason> {
+        match self.day.as_ref().map(|day| day.check(year, month)) {
+            Some(Err(reason)) => {
+                self.day = None;
+                Err(reason)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The day an entry was watched, as written in the list item.
+enum FilmDay {
+    /// A `14th:` prefix, relative to the month section the item is in.
+    DayOfMonth(i8),
+    /// A trailing ISO date after the rating, such as `(2024-03-14)`.
+    Date(Date),
+}
+
+impl FilmDay {
+    fn day(&self) -> i8 {
+        match self {
+            Self::DayOfMonth(day) => *day,
+            Self::Date(date) => date.day(),
+        }
+    }
+
+    fn check(&self, year: i16, month: &MonthOfYear) -> Result<(), ParseWarningReason> {
+        match self {
+            Self::DayOfMonth(day) => Date::new(year, month.number(), *day)
+                .map(|_| ())
+                .map_err(|_| ParseWarningReason::InvalidDay(day.to_string())),
+            Self::Date(date) if date.year() == year && date.month() == month.number() => Ok(()),
+            Self::Date(date) => Err(ParseWarningReason::DateOutsideMonth(date.to_string())),
+        }
+    }
 }
 
 struct Month {
@@ -18,64 +82,457 @@ struct Year {
     months: Vec<Month>,
 }
 
-fn get_films_from_ast<'a>(root: &'a AstNode<'a>) -> Vec<WatchedFilm> {
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseReport {
+    pub warnings: Vec<ParseWarning>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ParseWarning {
+    pub line: usize,
+    pub column: usize,
+    pub text: String,
+    pub reason: ParseWarningReason,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum ParseWarningReason {
+    MissingYear,
+    InvalidYear(String),
+    MissingMonth,
+    InvalidMonth(String),
+    MonthBeforeYear,
+    ListBeforeYear,
+    ListBeforeMonth,
+    UnrecognisedItem,
+    MissingRating,
+    InvalidRating(String),
+    InvalidDay(String),
+    DateOutsideMonth(String),
+}
+
+impl From<TryFromMonthOfYearError> for ParseWarningReason {
+    fn from(value: TryFromMonthOfYearError) -> Self {
+        match value {
+            TryFromMonthOfYearError::EmptyString => Self::MissingMonth,
+            TryFromMonthOfYearError::InvalidMonth(month) => Self::InvalidMonth(month),
+        }
+    }
+}
+
+impl From<TryFromRatingError> for ParseWarningReason {
+    fn from(value: TryFromRatingError) -> Self {
+        match value {
+            TryFromRatingError::EmptyString => Self::MissingRating,
+            TryFromRatingError::InvalidRating(rating) => Self::InvalidRating(rating),
+        }
+    }
+}
+
+impl ParseReport {
+    fn warn<'a>(
+        &mut self,
+        editor: &MarkdownEditor,
+        node: &'a AstNode<'a>,
+        reason: ParseWarningReason,
+    ) {
+        let sourcepos = sourcepos(node);
+
+        self.warnings.push(ParseWarning {
+            line: sourcepos.start.line,
+            column: sourcepos.start.column,
+            text: editor.text(sourcepos).trim().to_string(),
+            reason,
+        });
+    }
+}
+
+/// The side whose version of a film entry is kept when both changed it.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeSide {
+    Local,
+    Remote,
+}
+
+/// A film entry that was changed differently locally and remotely since the common base. `None`
+/// means the entry did not exist, or was deleted, on that side.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub base: Option<WatchedFilm>,
+    pub local: Option<WatchedFilm>,
+    pub remote: Option<WatchedFilm>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MergeResult {
+    pub markdown: String,
+    pub conflicts: Vec<MergeConflict>,
+}
+
+struct FilmItem<'a> {
+    year_heading: &'a AstNode<'a>,
+    month_heading: &'a AstNode<'a>,
+    list: &'a AstNode<'a>,
+    item: &'a AstNode<'a>,
+}
+
+fn node_text<'a>(node: &'a AstNode<'a>) -> Option<String> {
+    match node.first_child()?.data.borrow().value {
+        NodeValue::Text(ref text) => Some(text.to_string()),
+        _ => None,
+    }
+}
+
+fn parse_film_item<'a>(
+    list_item: &'a AstNode<'a>,
+    scale: &RatingScale,
+) -> Result<Film, ParseWarningReason> {
+    let text = match list_item.data.borrow().value {
+        NodeValue::Item(_)
+            if let Some(paragraph) = list_item.first_child()
+                && let NodeValue::Paragraph = paragraph.data.borrow().value
+                && let Some(text) = node_text(paragraph) =>
+        {
+            text
+        }
+        _ => return Err(ParseWarningReason::UnrecognisedItem),
+    };
+
+    let (day, text) = match text.split_once(':') {
+        Some((prefix, rest)) if let Some(day) = parse_day_of_month(prefix) => {
+            (Some(FilmDay::DayOfMonth(day)), rest)
+        }
+        _ => (None, text.as_str()),
+    };
+
+    // Titles such as "Spider-Man" contain dashes too, so the title ends at the last " - " that is
+    // followed by a rating.
+    let (film, details) = match text.rmatch_indices(" - ").find_map(|(index, separator)| {
+        let details = parse_film_details(&text[index + separator.len()..], scale).ok()?;
+        Some((&text[..index], details))
+    }) {
+        Some(split) => split,
+        None => {
+            let (film, rest) = text
+                .split_once('-')
+                .ok_or(ParseWarningReason::MissingRating)?;
+            (film, parse_film_details(rest, scale)?)
+        }
+    };
+
+    let (title, rewatch) = split_rewatch_marker(film);
+
+    Ok(Film {
+        title: title.to_string(),
+        rating: details.rating,
+        day: day.or(details.date.map(FilmDay::Date)),
+        rewatch,
+        tags: details.tags,
+        companions: details.companions,
+        notes: None,
+    })
+}
+
+/// What is written after the title: the rating, then any tags and companions, and the date.
+struct FilmDetails {
+    rating: Rating,
+    tags: Vec<String>,
+    companions: Vec<String>,
+    date: Option<Date>,
+}
+
+fn parse_film_details(text: &str, scale: &RatingScale) -> Result<FilmDetails, ParseWarningReason> {
+    let (rating_str, tags, companions) = split_tags(text);
+
+    let (rating_str, date) = match split_trailing_date(&rating_str) {
+        Some((rest, date)) => (rest, Some(date)),
+        None => (rating_str.as_str(), None),
+    };
+
+    Ok(FilmDetails {
+        rating: scale.parse(rating_str)?,
+        tags,
+        companions,
+        date,
+    })
+}
+
+/// Splits `#tag` and `@companion` words out of the text after the title, which is left with the
+/// rest of the words. Only the text after the title is searched, so titles such as `#Alive` are
+/// kept whole.
+fn split_tags(text: &str) -> (String, Vec<String>, Vec<String>) {
+    let mut rest = Vec::new();
+    let mut tags = Vec::new();
+    let mut companions = Vec::new();
+
+    for word in text.split_whitespace() {
+        match word.split_at_checked(1) {
+            Some(("#", tag)) if is_tag_name(tag) => tags.push(tag.to_string()),
+            Some(("@", name)) if is_tag_name(name) => companions.push(name.to_string()),
+            _ => rest.push(word),
+        }
+    }
+
+    (rest.join(" "), tags, companions)
+}
+
+/// Checks the tags and companions can be written so they are read back from the file, rather than
+/// left in the rating.
+pub fn check_tags(tags: &[String], companions: &[String]) -> Result<(), String> {
+    let tags = tags.iter().map(|tag| ("#", tag));
+    let companions = companions.iter().map(|name| ("@", name));
+
+    match tags.chain(companions).find(|(_, name)| !is_tag_name(name)) {
+        Some((prefix, name)) => Err(format!(
+            "{prefix}{name} can only contain letters, numbers, '-' and '_'"
+        )),
+        None => Ok(()),
+    }
+}
+
+fn is_tag_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '-' || c == '_')
+}
+
+/// The content nested under an item after its first paragraph, such as a review or sub-bullets,
+/// with the item's indentation removed and its inline markdown kept as written.
+fn item_notes<'a>(editor: &MarkdownEditor, item: &'a AstNode<'a>) -> Option<String> {
+    let paragraph = item.first_child()?;
+    let first = paragraph.next_sibling()?;
+    let last = item.last_child()?;
+    let indent = sourcepos(paragraph).start.column.saturating_sub(1);
+
+    let text = &editor.source
+        [editor.line_start(sourcepos(first).start.line)..editor.line_end(sourcepos(last).end.line)];
+
+    let notes = text
+        .lines()
+        .map(|line| {
+            let spaces = line.len() - line.trim_start_matches(' ').len();
+            &line[spaces.min(indent)..]
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    let notes = notes.trim_end();
+
+    (!notes.is_empty()).then(|| notes.to_string())
+}
+
+/// Splits a trailing `(rewatch)` marker, in any case, off the end of a title.
+fn split_rewatch_marker(title: &str) -> (&str, bool) {
+    let title = title.trim();
+
+    match title
+        .len()
+        .checked_sub(REWATCH_MARKER.len())
+        .filter(|&start| title.is_char_boundary(start))
+    {
+        Some(start) if title[start..].eq_ignore_ascii_case(REWATCH_MARKER) => {
+            (title[..start].trim_end(), true)
+        }
+        _ => (title, false),
+    }
+}
+
+/// Parses an ordinal day such as `14th`, so a title that happens to contain a colon is not
+/// mistaken for a day.
+fn parse_day_of_month(prefix: &str) -> Option<i8> {
+    let prefix = prefix.trim().to_lowercase();
+    let digits = ["st", "nd", "rd", "th"]
+        .iter()
+        .find_map(|suffix| prefix.strip_suffix(suffix))?;
+
+    digits
+        .parse::<i8>()
+        .ok()
+        .filter(|day| (1..=31).contains(day))
+}
+
+/// Splits a trailing ISO date, written as `good 2024-03-14`, `good - 2024-03-14` or
+/// `good (2024-03-14)`, off the end of the rating.
+fn split_trailing_date(text: &str) -> Option<(&str, Date)> {
+    let text = text.trim_end();
+
+    let (rest, candidate) = match text.strip_suffix(')') {
+        Some(text) => text.rsplit_once('(')?,
+        None => text.rsplit_once(char::is_whitespace)?,
+    };
+
+    let candidate = candidate.trim();
+
+    if candidate.len() != "YYYY-MM-DD".len() {
+        return None;
+    }
+
+    let date = candidate.parse::<Date>().ok()?;
+
+    Some((rest.trim_end().trim_end_matches('-'), date))
+}
+
+fn ordinal(day: i8) -> String {
+    let suffix = match (day % 10, day % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+
+    format!("{day}{suffix}")
+}
+
+fn format_film_item(film: &WatchedFilm, scale: &RatingScale) -> String {
+    let day = film
+        .day_of_month_watched
+        .map(|day| format!("{}: ", ordinal(day)))
+        .unwrap_or_default();
+    let rewatch = if film.rewatch {
+        format!(" {REWATCH_MARKER}")
+    } else {
+        String::new()
+    };
+
+    let tags = film
+        .tags
+        .iter()
+        .map(|tag| format!(" #{tag}"))
+        .chain(film.companions.iter().map(|name| format!(" @{name}")))
+        .collect::<String>();
+
+    format!(
+        "{}{}{} - {}{}",
+        day,
+        film.title,
+        rewatch,
+        scale.label(&film.rating),
+        tags
+    )
+}
+
+/// Formats the item followed by its notes, which are separated by a blank line so they are not
+/// read as a continuation of the item's paragraph, and indented to nest under it.
+fn format_film_content(film: &WatchedFilm, indent: usize, scale: &RatingScale) -> String {
+    let item = format_film_item(film, scale);
+
+    match film.notes.as_deref().map(str::trim_end) {
+        Some(notes) if !notes.is_empty() => {
+            let notes = notes
+                .lines()
+                .map(|line| match line.trim_end() {
+                    "" => String::new(),
+                    line => format!("{}{}", " ".repeat(indent), line),
+                })
+                .collect::<Vec<_>>()
+                .join("\n");
+
+            format!("{}\n\n{}", item, notes)
+        }
+        _ => item,
+    }
+}
+
+fn format_film_entry(prefix: &str, film: &WatchedFilm, scale: &RatingScale) -> String {
+    format!(
+        "{}{}\n",
+        prefix,
+        format_film_content(film, prefix.len(), scale)
+    )
+}
+
+fn get_films_from_ast<'a>(
+    root: &'a AstNode<'a>,
+    editor: &MarkdownEditor,
+    scale: &RatingScale,
+) -> (Vec<WatchedFilm>, ParseReport) {
     let mut years: Vec<Year> = Vec::new();
+    let mut report = ParseReport::default();
+    // Set by a heading that isn't a year, so its entries aren't filed under the year before it.
+    let mut in_invalid_year = false;
+    // Likewise for a heading that isn't a month, so its entries aren't filed under the last month.
+    let mut in_invalid_month = false;
 
     for node in root.children() {
         match &node.data.borrow().value {
-            NodeValue::Heading(NodeHeading { level: 2, .. })
-                if let Some(text_node) = node.first_child()
-                    && let NodeValue::Text(ref text) = text_node.data.borrow().value
-                    && let Ok(year) = i16::from_str(text.trim()) =>
-            {
-                let new_year = Year {
-                    name: year,
-                    months: vec![],
-                };
-                years.push(new_year);
-            }
-            NodeValue::Heading(NodeHeading { level: 3, .. })
-                if let Some(text_node) = node.first_child()
-                    && let NodeValue::Text(ref text) = text_node.data.borrow().value
-                    && let Ok(month) = MonthOfYear::try_from(text.trim())
-                    && let Some(current_year) = years.last_mut() =>
-            {
-                let new_month = Month {
-                    month_of_year: month,
-                    films: vec![],
-                };
-                current_year.months.push(new_month);
+            NodeValue::Heading(NodeHeading { level: 2, .. }) => {
+                let text = node_text(node).unwrap_or_default();
+                in_invalid_month = false;
+
+                match (text.trim(), i16::from_str(text.trim())) {
+                    (_, Ok(year)) => {
+                        let new_year = Year {
+                            name: year,
+                            months: vec![],
+                        };
+                        years.push(new_year);
+                        in_invalid_year = false;
+                    }
+                    ("", Err(_)) => {
+                        report.warn(editor, node, ParseWarningReason::MissingYear);
+                        in_invalid_year = true;
+                    }
+                    (text, Err(_)) => {
+                        let reason = ParseWarningReason::InvalidYear(text.to_string());
+                        report.warn(editor, node, reason);
+                        in_invalid_year = true;
+                    }
+                }
             }
-            NodeValue::List(_)
-                if let Some(current_year) = years.last_mut()
-                    && let Some(current_month) = current_year.months.last_mut() =>
-            {
-                for list_item in node.children() {
-                    match list_item.data.borrow().value {
-                        NodeValue::Item(_)
-                            if let Some(paragraph) = list_item.first_child()
-                                && let NodeValue::Paragraph = paragraph.data.borrow().value
-                                && let Some(text_node) = paragraph.first_child()
-                                && let NodeValue::Text(ref text) =
-                                    text_node.data.borrow().value
-                                && let Some((film, rating_str)) = text.split_once('-')
-                                && let Ok(rating) = Rating::try_from(rating_str) =>
-                        {
-                            let film = Film {
-                                title: film.trim().to_string(),
-                                rating,
-                            };
-                            current_month.films.push(film);
-                        }
-                        _ => {}
+            NodeValue::Heading(NodeHeading { level: 3, .. }) | NodeValue::List(_)
+                if in_invalid_year => {}
+            NodeValue::Heading(NodeHeading { level: 3, .. }) => {
+                let text = node_text(node).unwrap_or_default();
+
+                match (MonthOfYear::try_from(text.as_str()), years.last_mut()) {
+                    (Ok(month), Some(current_year)) => {
+                        let new_month = Month {
+                            month_of_year: month,
+                            films: vec![],
+                        };
+                        current_year.months.push(new_month);
+                        in_invalid_month = false;
+                    }
+                    (Ok(_), None) => report.warn(editor, node, ParseWarningReason::MonthBeforeYear),
+                    (Err(err), _) => {
+                        report.warn(editor, node, err.into());
+                        in_invalid_month = true;
                     }
                 }
             }
+            NodeValue::List(_) if in_invalid_month => {}
+            NodeValue::List(_) => match years.last_mut() {
+                None => report.warn(editor, node, ParseWarningReason::ListBeforeYear),
+                Some(current_year) => match current_year.months.last_mut() {
+                    None => report.warn(editor, node, ParseWarningReason::ListBeforeMonth),
+                    Some(current_month) => {
+                        for list_item in node.children() {
+                            match parse_film_item(list_item, scale) {
+                                Ok(mut film) => {
+                                    film.notes = item_notes(editor, list_item);
+
+                                    // A bad day is reported but the entry is kept without it.
+                                    if let Err(reason) = film
+                                        .check_day(current_year.name, &current_month.month_of_year)
+                                    {
+                                        report.warn(editor, list_item, reason);
+                                    }
+
+                                    current_month.films.push(film)
+                                }
+                                Err(reason) => report.warn(editor, list_item, reason),
+                            }
+                        }
+                    }
+                },
+            },
             _ => {}
         }
     }
 
-    years
+    let films = years
         .iter()
         .flat_map(|year| {
             year.months.iter().flat_map(|month| {
@@ -84,103 +541,1490 @@ fn get_films_from_ast<'a>(root: &'a AstNode<'a>) -> Vec<WatchedFilm> {
                     rating: film.rating.clone(),
                     year_watched: year.name,
                     month_of_year_watched: month.month_of_year.clone(),
+                    day_of_month_watched: film.day.as_ref().map(FilmDay::day),
+                    rewatch: film.rewatch,
+                    tags: film.tags.clone(),
+                    companions: film.companions.clone(),
+                    notes: film.notes.clone(),
                 })
             })
         })
-        .collect()
+        .collect();
+
+    (films, report)
 }
 
-pub fn parse_films_from_markdown(markdown: impl Into<String>) -> Vec<WatchedFilm> {
-    let arena = Arena::new();
-    let markdown = markdown.into();
-    let ast = parse_document(&arena, &markdown, &Options::default());
+struct MonthSection<'a> {
+    month: MonthOfYear,
+    heading: &'a AstNode<'a>,
+    last_list: Option<&'a AstNode<'a>>,
+    end: Option<&'a AstNode<'a>>,
+}
 
-    get_films_from_ast(ast)
+struct YearSection<'a> {
+    year: i16,
+    heading: &'a AstNode<'a>,
+    months: Vec<MonthSection<'a>>,
+    end: Option<&'a AstNode<'a>>,
 }
 
-pub fn _write_film_to_markdown(markdown: impl Into<String>, film: WatchedFilm) -> String {
-    let arena = Arena::new();
-    let markdown = markdown.into();
-    let ast = parse_document(&arena, &markdown, &Options::default());
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum SectionOrder {
+    Ascending,
+    Descending,
+}
 
-    let mut films = get_films_from_ast(ast);
+impl SectionOrder {
+    /// Infers the order from the year headings, falling back to the months of a single year and
+    /// then to ascending, which is what appending new entries to the end of a file produces.
+    fn detect(years: &[YearSection]) -> Self {
+        let descending = match years {
+            [first, .., last] => first.year > last.year,
+            [year] if let [first, .., last] = year.months.as_slice() => first.month > last.month,
+            _ => false,
+        };
 
-    films.sort_by(|a, b| {
-        a.year_watched
-            .cmp(&b.year_watched)
-            .then(a.month_of_year_watched.cmp(&b.month_of_year_watched))
-    });
+        if descending {
+            Self::Descending
+        } else {
+            Self::Ascending
+        }
+    }
 
-    let most_recent_film = films.last();
+    fn comes_before<T: Ord>(self, a: &T, b: &T) -> bool {
+        match self {
+            Self::Ascending => a < b,
+            Self::Descending => a > b,
+        }
+    }
+}
 
-    let now = jiff::Zoned::now().date();
-    let current_year = now.year();
-    let current_month = MonthOfYear::try_from(now.month()).unwrap();
+fn heading_level<'a>(node: &'a AstNode<'a>) -> Option<u8> {
+    match node.data.borrow().value {
+        NodeValue::Heading(NodeHeading { level, .. }) => Some(level),
+        _ => None,
+    }
+}
 
-    let year_to_add = match most_recent_film {
-        Some(film) if film.year_watched == current_year => None,
-        _ => Some(current_year),
-    };
+/// The first following sibling that closes a section opened by a heading of the given level.
+fn section_end<'a>(heading: &'a AstNode<'a>, level: u8) -> Option<&'a AstNode<'a>> {
+    heading
+        .following_siblings()
+        .skip(1)
+        .find(|&node| heading_level(node).is_some_and(|next_level| next_level <= level))
+}
+
+fn get_sections_from_ast<'a>(root: &'a AstNode<'a>) -> Vec<YearSection<'a>> {
+    let mut years: Vec<YearSection<'a>> = Vec::new();
+    let mut in_year = false;
+    let mut in_invalid_month = false;
+
+    for node in root.children() {
+        match &node.data.borrow().value {
+            NodeValue::Heading(NodeHeading { level: 2, .. })
+                if let Some(year) = node_text(node)
+                    && let Ok(year) = i16::from_str(year.trim()) =>
+            {
+                years.push(YearSection {
+                    year,
+                    heading: node,
+                    months: vec![],
+                    end: section_end(node, 2),
+                });
+                in_year = true;
+                in_invalid_month = false;
+            }
+            NodeValue::Heading(NodeHeading { level: 2, .. }) => {
+                in_year = false;
+                in_invalid_month = false;
+            }
+            NodeValue::Heading(NodeHeading { level: 3, .. })
+                if let Some(month) = node_text(node)
+                    && let Ok(month) = MonthOfYear::try_from(month.as_str())
+                    && in_year
+                    && let Some(current_year) = years.last_mut() =>
+            {
+                current_year.months.push(MonthSection {
+                    month,
+                    heading: node,
+                    last_list: None,
+                    end: section_end(node, 3),
+                });
+                in_invalid_month = false;
+            }
+            NodeValue::Heading(NodeHeading { level: 3, .. }) => in_invalid_month = true,
+            NodeValue::List(_)
+                if in_year
+                    && !in_invalid_month
+                    && let Some(current_year) = years.last_mut()
+                    && let Some(current_month) = current_year.months.last_mut() =>
+            {
+                current_month.last_list = Some(node);
+            }
+            _ => {}
+        }
+    }
+
+    years
+}
 
-    let month_to_add = match most_recent_film {
-        Some(film) if film.month_of_year_watched == current_month => None,
-        _ => Some(current_month),
+/// Finds the item for `film`, skipping the first `occurrence` items in its month that are written
+/// the same way.
+fn find_film_item<'a>(
+    root: &'a AstNode<'a>,
+    film: &WatchedFilm,
+    mut occurrence: usize,
+    scale: &RatingScale,
+) -> Option<FilmItem<'a>> {
+    let mut current_year: Option<(i16, &'a AstNode<'a>)> = None;
+    let mut current_month: Option<(MonthOfYear, &'a AstNode<'a>)> = None;
+
+    for node in root.children() {
+        match &node.data.borrow().value {
+            NodeValue::Heading(NodeHeading { level: 2, .. })
+                if let Some(text_node) = node.first_child()
+                    && let NodeValue::Text(ref text) = text_node.data.borrow().value
+                    && let Ok(year) = i16::from_str(text.trim()) =>
+            {
+                current_year = Some((year, node));
+                current_month = None;
+            }
+            NodeValue::Heading(NodeHeading { level: 2, .. }) => {
+                current_year = None;
+                current_month = None;
+            }
+            NodeValue::Heading(NodeHeading { level: 3, .. })
+                if let Some(text_node) = node.first_child()
+                    && let NodeValue::Text(ref text) = text_node.data.borrow().value
+                    && let Ok(month) = MonthOfYear::try_from(text.trim())
+                    && current_year.is_some() =>
+            {
+                current_month = Some((month, node));
+            }
+            NodeValue::Heading(NodeHeading { level: 3, .. }) => current_month = None,
+            NodeValue::List(_)
+                if let Some((year, year_heading)) = current_year
+                    && let Some((ref month, month_heading)) = current_month
+                    && year == film.year_watched
+                    && *month == film.month_of_year_watched =>
+            {
+                let item = node
+                    .children()
+                    .filter(|&list_item| match parse_film_item(list_item, scale) {
+                        Ok(mut parsed) => {
+                            // Matched as it was parsed, without a day the month doesn't have.
+                            let _ = parsed.check_day(year, month);
+                            parsed.is(film)
+                        }
+                        Err(_) => false,
+                    })
+                    .find(|_| match occurrence {
+                        0 => true,
+                        _ => {
+                            occurrence -= 1;
+                            false
+                        }
+                    });
+
+                if let Some(item) = item {
+                    return Some(FilmItem {
+                        year_heading,
+                        month_heading,
+                        list: node,
+                        item,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+fn is_section_empty<'a>(heading: &'a AstNode<'a>) -> bool {
+    let NodeValue::Heading(NodeHeading { level, .. }) = heading.data.borrow().value else {
+        return false;
     };
 
-    if let Some(year_to_add) = year_to_add {
-        let heading = arena.alloc(AstNode::from(NodeValue::Heading(NodeHeading {
-            level: 2,
-            ..NodeHeading::default()
-        })));
+    match heading.next_sibling() {
+        None => true,
+        Some(next) => matches!(
+            next.data.borrow().value,
+            NodeValue::Heading(NodeHeading { level: next_level, .. }) if next_level <= level
+        ),
+    }
+}
+
+/// Collects byte-range replacements against the original markdown so that everything outside
+/// the touched ranges is written back exactly as it was read.
+struct MarkdownEditor<'s> {
+    source: &'s str,
+    line_starts: Vec<usize>,
+    splices: Vec<(Range<usize>, String)>,
+}
 
-        let text = arena.alloc(AstNode::from(NodeValue::Text(
-            year_to_add.to_string().into(),
-        )));
+impl<'s> MarkdownEditor<'s> {
+    fn new(source: &'s str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
 
-        heading.append(text);
+        Self {
+            source,
+            line_starts,
+            splices: vec![],
+        }
+    }
 
-        ast.append(heading);
+    /// Byte offset of the start of a 1-based line, or the end of the source past the last line.
+    fn line_start(&self, line: usize) -> usize {
+        self.line_starts
+            .get(line.saturating_sub(1))
+            .copied()
+            .unwrap_or(self.source.len())
     }
 
-    if let Some(month_to_add) = month_to_add {
-        let heading = arena.alloc(AstNode::from(NodeValue::Heading(NodeHeading {
-            level: 3,
-            ..NodeHeading::default()
-        })));
+    /// Byte offset of the end of a 1-based line, excluding its line ending.
+    fn line_end(&self, line: usize) -> usize {
+        let start = self.line_start(line);
+        let end = self.line_start(line + 1);
+
+        start + self.source[start..end].trim_end_matches(['\r', '\n']).len()
+    }
 
-        let text = arena.alloc(AstNode::from(NodeValue::Text(
-            month_to_add.to_string().into(),
-        )));
+    fn offset(&self, position: LineColumn) -> usize {
+        self.line_start(position.line) + position.column.saturating_sub(1)
+    }
 
-        heading.append(text);
+    fn text(&self, sourcepos: Sourcepos) -> &'s str {
+        &self.source[self.offset(sourcepos.start)..self.line_end(sourcepos.end.line)]
+    }
 
-        ast.append(heading);
+    fn ends_with_newline(&self, offset: usize) -> bool {
+        offset == 0 || self.source[..offset].ends_with('\n')
     }
 
-    let list_item = arena.alloc(AstNode::from(NodeValue::Item(Default::default())));
+    fn replace(&mut self, range: Range<usize>, replacement: impl Into<String>) {
+        self.splices.push((range, replacement.into()));
+    }
 
-    let paragraph = arena.alloc(AstNode::from(NodeValue::Paragraph));
-    let text = arena.alloc(AstNode::from(NodeValue::Text(
-        format!("{} - {}", film.title, film.rating).into(),
-    )));
+    fn insert(&mut self, offset: usize, text: impl Into<String>) {
+        self.replace(offset..offset, text);
+    }
 
-    paragraph.append(text);
-    list_item.append(paragraph);
+    /// Inserts whole lines before the given 1-based line, adding a line break first if the
+    /// preceding content does not end with one.
+    fn insert_lines(&mut self, line: usize, text: &str) {
+        let offset = self.line_start(line);
 
-    match ast.last_child() {
-        Some(list) if matches!(list.data.borrow().value, NodeValue::List(_)) => {
-            list.append(list_item)
+        if self.ends_with_newline(offset) {
+            self.insert(offset, text);
+        } else {
+            self.insert(offset, format!("\n{}", text));
         }
-        _ => {
-            let list = arena.alloc(AstNode::from(NodeValue::List(Default::default())));
-            list.append(list_item);
-            ast.append(list);
+    }
+
+    fn blank_line_separator(&self, offset: usize) -> &'static str {
+        let preceding = &self.source[..offset];
+        let content_len = preceding.trim_end_matches(['\r', '\n']).len();
+
+        match preceding[content_len..].matches('\n').count() {
+            _ if content_len == 0 => "",
+            0 => "\n\n",
+            1 => "\n",
+            _ => "",
         }
-    };
+    }
+
+    /// Inserts a block before the given 1-based line, or at the end of the source if there is
+    /// no line, keeping it separated from its neighbours by blank lines.
+    fn insert_block(&mut self, line: Option<usize>, block: &str) {
+        match line {
+            Some(line) => {
+                let offset = self.line_start(line);
+                let separator = self.blank_line_separator(offset);
+                self.insert(offset, format!("{}{}\n", separator, block));
+            }
+            None => {
+                let offset = self.source.len();
+                let separator = self.blank_line_separator(offset);
+                self.insert(offset, format!("{}{}", separator, block));
+            }
+        }
+    }
+
+    fn remove_lines(&mut self, lines: Range<usize>) {
+        self.replace(self.line_start(lines.start)..self.line_start(lines.end), "");
+    }
+
+    fn finish(self) -> String {
+        let mut splices = self.splices;
+        splices.sort_by_key(|(range, _)| (range.start, range.end));
+
+        let mut output = String::with_capacity(self.source.len());
+        let mut cursor = 0;
+
+        for (range, replacement) in splices {
+            output.push_str(&self.source[cursor..range.start.max(cursor)]);
+            output.push_str(&replacement);
+            cursor = cursor.max(range.end);
+        }
+
+        output.push_str(&self.source[cursor..]);
+
+        output
+    }
+}
 
-    let mut output = "".to_string();
+fn sourcepos<'a>(node: &'a AstNode<'a>) -> Sourcepos {
+    node.data.borrow().sourcepos
+}
 
-    format_commonmark(ast, &Options::default(), &mut output).expect("failed to format");
+/// The last line with content in a node. A list or item followed by a blank line ends at column 0
+/// of that blank line, which belongs to the gap after it rather than to the node.
+fn content_end_line<'a>(node: &'a AstNode<'a>) -> usize {
+    let end = sourcepos(node).end;
 
-    output
+    match end.column {
+        0 => end.line.saturating_sub(1),
+        _ => end.line,
+    }
+}
+
+/// The text that precedes an item's content on its line, e.g. `- ` or `  * `.
+fn item_prefix<'a>(editor: &MarkdownEditor, item: &'a AstNode<'a>) -> String {
+    let item_start = sourcepos(item).start;
+    let content_start = item
+        .first_child()
+        .map(|paragraph| editor.offset(sourcepos(paragraph).start))
+        .unwrap_or(editor.offset(item_start) + 2);
+
+    editor.source[editor.line_start(item_start.line)..content_start].to_string()
+}
+
+pub fn parse_films_from_markdown(
+    markdown: impl Into<String>,
+    scale: &RatingScale,
+) -> (Vec<WatchedFilm>, ParseReport) {
+    let arena = Arena::new();
+    let markdown = markdown.into();
+    let ast = parse_document(&arena, &markdown, &Options::default());
+
+    get_films_from_ast(ast, &MarkdownEditor::new(&markdown), scale)
+}
+
+pub fn write_film_to_markdown(
+    markdown: impl Into<String>,
+    film: WatchedFilm,
+    scale: &RatingScale,
+) -> String {
+    let arena = Arena::new();
+    let markdown = markdown.into();
+    let ast = parse_document(&arena, &markdown, &Options::default());
+
+    let years = get_sections_from_ast(ast);
+    let order = SectionOrder::detect(&years);
+
+    let mut editor = MarkdownEditor::new(&markdown);
+
+    let Some(year) = years.iter().find(|year| year.year == film.year_watched) else {
+        let block = format!(
+            "## {}\n\n### {}\n\n{}",
+            film.year_watched,
+            film.month_of_year_watched,
+            format_film_entry("- ", &film, scale)
+        );
+
+        let before = years
+            .iter()
+            .find(|year| order.comes_before(&film.year_watched, &year.year))
+            .map(|year| year.heading)
+            .or(years.last().and_then(|year| year.end));
+
+        editor.insert_block(before.map(|node| sourcepos(node).start.line), &block);
+
+        return editor.finish();
+    };
+
+    let Some(month) = year
+        .months
+        .iter()
+        .find(|month| month.month == film.month_of_year_watched)
+    else {
+        let block = format!(
+            "### {}\n\n{}",
+            film.month_of_year_watched,
+            format_film_entry("- ", &film, scale)
+        );
+
+        let before = year
+            .months
+            .iter()
+            .find(|month| order.comes_before(&film.month_of_year_watched, &month.month))
+            .map(|month| month.heading)
+            .or(year.end);
+
+        editor.insert_block(before.map(|node| sourcepos(node).start.line), &block);
+
+        return editor.finish();
+    };
+
+    // Entries with a day go before the first later day in the month, so the list stays in order.
+    let later_item = month
+        .last_list
+        .zip(film.day_of_month_watched)
+        .and_then(|(list, day)| {
+            list.children().find(|&item| {
+                parse_film_item(item, scale)
+                    .ok()
+                    .and_then(|parsed| parsed.day)
+                    .is_some_and(|parsed| parsed.day() > day)
+            })
+        });
+
+    match (month.last_list, later_item) {
+        (_, Some(item)) => {
+            let prefix = item_prefix(&editor, item);
+
+            editor.insert_lines(
+                sourcepos(item).start.line,
+                &format_film_entry(&prefix, &film, scale),
+            );
+        }
+        (Some(list), None) => {
+            let prefix = list
+                .last_child()
+                .map(|item| item_prefix(&editor, item))
+                .unwrap_or("- ".to_string());
+
+            editor.insert_lines(
+                content_end_line(list) + 1,
+                &format_film_entry(&prefix, &film, scale),
+            );
+        }
+        (None, None) => editor.insert_block(
+            month.end.map(|node| sourcepos(node).start.line),
+            &format_film_entry("- ", &film, scale),
+        ),
+    }
+
+    editor.finish()
+}
+
+/// Replaces the item for `film`, along with any notes nested under it, with `edited`.
+pub fn edit_film_in_markdown(
+    markdown: impl Into<String>,
+    film: &WatchedFilm,
+    edited: &WatchedFilm,
+    scale: &RatingScale,
+) -> Option<String> {
+    edit_film_occurrence_in_markdown(markdown, film, 0, edited, scale)
+}
+
+fn edit_film_occurrence_in_markdown(
+    markdown: impl Into<String>,
+    film: &WatchedFilm,
+    occurrence: usize,
+    edited: &WatchedFilm,
+    scale: &RatingScale,
+) -> Option<String> {
+    let arena = Arena::new();
+    let markdown = markdown.into();
+    let ast = parse_document(&arena, &markdown, &Options::default());
+
+    let film_item = find_film_item(ast, film, occurrence, scale)?;
+    let paragraph = sourcepos(film_item.item.first_child()?);
+    let content_end = content_end_line(film_item.item.last_child()?);
+
+    let mut editor = MarkdownEditor::new(&markdown);
+    editor.replace(
+        editor.offset(paragraph.start)..editor.line_end(content_end),
+        format_film_content(edited, paragraph.start.column.saturating_sub(1), scale),
+    );
+
+    Some(editor.finish())
+}
+
+pub fn delete_film_from_markdown(
+    markdown: impl Into<String>,
+    film: &WatchedFilm,
+    scale: &RatingScale,
+) -> Option<String> {
+    delete_film_occurrence_from_markdown(markdown, film, 0, scale)
+}
+
+fn delete_film_occurrence_from_markdown(
+    markdown: impl Into<String>,
+    film: &WatchedFilm,
+    occurrence: usize,
+    scale: &RatingScale,
+) -> Option<String> {
+    let arena = Arena::new();
+    let markdown = markdown.into();
+    let ast = parse_document(&arena, &markdown, &Options::default());
+
+    let film_item = find_film_item(ast, film, occurrence, scale)?;
+    let mut editor = MarkdownEditor::new(&markdown);
+
+    // The last item takes the gap before it rather than the blank line after the list, which
+    // separates the list from what follows.
+    let item = sourcepos(film_item.item);
+    let lines = match (
+        film_item.item.previous_sibling(),
+        film_item.item.next_sibling(),
+    ) {
+        (Some(previous), None) => {
+            content_end_line(previous) + 1..content_end_line(film_item.item) + 1
+        }
+        _ => item.start.line..item.end.line + 1,
+    };
+    film_item.item.detach();
+
+    if film_item.list.first_child().is_some() {
+        editor.remove_lines(lines);
+        return Some(editor.finish());
+    }
+
+    // The sibling before the first removed node is recorded before it is detached, as a detached
+    // node no longer has siblings.
+    let next = film_item.list.next_sibling();
+    let mut first_removed = film_item.list;
+    let mut previous = film_item.list.previous_sibling();
+    film_item.list.detach();
+
+    if is_section_empty(film_item.month_heading) {
+        first_removed = film_item.month_heading;
+        previous = film_item.month_heading.previous_sibling();
+        film_item.month_heading.detach();
+
+        if is_section_empty(film_item.year_heading) {
+            first_removed = film_item.year_heading;
+            previous = film_item.year_heading.previous_sibling();
+        }
+    }
+
+    match next {
+        Some(next) => {
+            editor.remove_lines(sourcepos(first_removed).start.line..sourcepos(next).start.line)
+        }
+        None => {
+            let start = match previous {
+                Some(previous) => editor.line_end(content_end_line(previous)),
+                None => 0,
+            };
+            editor.replace(start..markdown.len(), if start == 0 { "" } else { "\n" });
+        }
+    }
+
+    Some(editor.finish())
+}
+
+pub fn apply_change_to_markdown(
+    markdown: impl Into<String>,
+    change: &FilmChange,
+    scale: &RatingScale,
+) -> Option<String> {
+    match change {
+        FilmChange::Add(film) => Some(write_film_to_markdown(markdown, film.clone(), scale)),
+        FilmChange::Edit {
+            film,
+            title,
+            rating,
+            tags,
+            companions,
+            notes,
+        } => edit_film_in_markdown(
+            markdown,
+            film,
+            &WatchedFilm {
+                title: title.clone(),
+                rating: rating.clone(),
+                tags: tags.clone(),
+                companions: companions.clone(),
+                notes: notes.clone(),
+                ..film.clone()
+            },
+            scale,
+        ),
+        FilmChange::Delete(film) => delete_film_from_markdown(markdown, film, scale),
+    }
+}
+
+fn is_same_entry(a: &WatchedFilm, b: &WatchedFilm) -> bool {
+    a.year_watched == b.year_watched
+        && a.month_of_year_watched == b.month_of_year_watched
+        && a.day_of_month_watched == b.day_of_month_watched
+        && a.title == b.title
+}
+
+/// Whether the two entries are written the same way, so that [`find_film_item`] can only tell
+/// them apart by which comes first.
+fn is_same_item(a: &WatchedFilm, b: &WatchedFilm) -> bool {
+    is_same_entry(a, b) && a.rating.value == b.rating.value && a.rewatch == b.rewatch
+}
+
+type MonthKey = (i16, i8);
+
+fn films_by_month(films: &[WatchedFilm]) -> BTreeMap<MonthKey, Vec<&WatchedFilm>> {
+    let mut months: BTreeMap<MonthKey, Vec<&WatchedFilm>> = BTreeMap::new();
+
+    for film in films {
+        months
+            .entry((film.year_watched, film.month_of_year_watched.number()))
+            .or_default()
+            .push(film);
+    }
+
+    months
+}
+
+/// Pairs each entry of a month in `base` with the entry it became in `other`, or `None` if it was
+/// removed. Entries with the same date and title are matched in the order they are written, so
+/// two viewings of a film in a month are kept apart. The entries left between two matches are
+/// paired by position when they have the same day, so an entry whose title was edited is still
+/// the same entry. Entries of `other` that no entry is paired with were added.
+fn align_entries(base: &[&WatchedFilm], other: &[&WatchedFilm]) -> Vec<Option<usize>> {
+    let (n, m) = (base.len(), other.len());
+
+    // The longest run of matching entries in `base[i..]` and `other[j..]`.
+    let mut lengths = vec![vec![0; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if is_same_entry(base[i], other[j]) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut matches = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if is_same_entry(base[i], other[j]) {
+            matches.push((i, j));
+            i += 1;
+            j += 1;
+        } else if lengths[i + 1][j] >= lengths[i][j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    let mut pairs = vec![None; n];
+    let (mut start_i, mut start_j) = (0, 0);
+
+    for (end_i, end_j) in matches.into_iter().chain([(n, m)]) {
+        for (i, j) in (start_i..end_i).zip(start_j..end_j) {
+            if base[i].day_of_month_watched == other[j].day_of_month_watched {
+                pairs[i] = Some(j);
+            }
+        }
+
+        if end_i < n {
+            pairs[end_i] = Some(end_j);
+        }
+
+        (start_i, start_j) = (end_i + 1, end_j + 1);
+    }
+
+    pairs
+}
+
+/// An entry of the remote file to replace with the local version, or delete if it is `None`.
+struct RemoteEdit<'a> {
+    base: &'a WatchedFilm,
+    remote: &'a WatchedFilm,
+    /// How many entries before it in the month are written the same way.
+    occurrence: usize,
+    local: Option<WatchedFilm>,
+}
+
+/// Merges the film entries changed between `base` and `local` into `remote`, editing the remote
+/// file so its formatting is kept. Entries are matched within each month by
+/// [`align_entries`]. An entry changed differently on both sides is settled by `prefer`, or
+/// otherwise left as it is on the remote and reported as a conflict.
+pub fn merge_markdown(
+    base: &str,
+    local: &str,
+    remote: &str,
+    prefer: Option<MergeSide>,
+    scale: &RatingScale,
+) -> MergeResult {
+    let (base_films, _) = parse_films_from_markdown(base, scale);
+    let (local_films, _) = parse_films_from_markdown(local, scale);
+    let (remote_films, _) = parse_films_from_markdown(remote, scale);
+
+    let base_months = films_by_month(&base_films);
+    let local_months = films_by_month(&local_films);
+    let remote_months = films_by_month(&remote_films);
+
+    let months: BTreeSet<&MonthKey> = base_months
+        .keys()
+        .chain(local_months.keys())
+        .chain(remote_months.keys())
+        .collect();
+
+    let mut edits = Vec::new();
+    let mut additions = Vec::new();
+    let mut conflicts = Vec::new();
+
+    for month in months {
+        let base = base_months
+            .get(month)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let local = local_months
+            .get(month)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let remote = remote_months
+            .get(month)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+
+        let to_local = align_entries(base, local);
+        let to_remote = align_entries(base, remote);
+
+        for (i, &base_film) in base.iter().enumerate() {
+            let local_film = to_local[i].map(|j| local[j].clone());
+            let remote_index = to_remote[i];
+            let remote_film = remote_index.map(|k| remote[k].clone());
+
+            if local_film.as_ref() == Some(base_film) || local_film == remote_film {
+                continue;
+            }
+
+            if remote_film.as_ref() != Some(base_film) && prefer != Some(MergeSide::Local) {
+                if prefer.is_none() {
+                    conflicts.push(MergeConflict {
+                        base: Some(base_film.clone()),
+                        local: local_film,
+                        remote: remote_film,
+                    });
+                }
+
+                continue;
+            }
+
+            match remote_index {
+                Some(k) => edits.push(RemoteEdit {
+                    base: base_film,
+                    remote: remote[k],
+                    occurrence: remote[..k]
+                        .iter()
+                        .filter(|film| is_same_item(film, remote[k]))
+                        .count(),
+                    local: local_film,
+                }),
+                // Deleted on GitHub but edited locally, and the local version was chosen.
+                None => additions.extend(local_film),
+            }
+        }
+
+        // An entry added on both sides in the same way is only written once.
+        let mut added_remotely: Vec<&WatchedFilm> = (0..remote.len())
+            .filter(|k| !to_remote.contains(&Some(*k)))
+            .map(|k| remote[k])
+            .collect();
+
+        for (j, &film) in local.iter().enumerate() {
+            if to_local.contains(&Some(j)) {
+                continue;
+            }
+
+            match added_remotely.iter().position(|&added| added == film) {
+                Some(position) => {
+                    added_remotely.remove(position);
+                }
+                None => additions.push(film.clone()),
+            }
+        }
+    }
+
+    let mut markdown = remote.to_string();
+
+    // Later entries are edited first, so the earlier entries they are counted after still match.
+    for edit in edits.into_iter().rev() {
+        let merged = match &edit.local {
+            Some(local) => edit_film_occurrence_in_markdown(
+                &markdown,
+                edit.remote,
+                edit.occurrence,
+                local,
+                scale,
+            ),
+            None => {
+                delete_film_occurrence_from_markdown(&markdown, edit.remote, edit.occurrence, scale)
+            }
+        };
+
+        match merged {
+            Some(merged) => markdown = merged,
+            None => conflicts.push(MergeConflict {
+                base: Some(edit.base.clone()),
+                local: edit.local,
+                remote: Some(edit.remote.clone()),
+            }),
+        }
+    }
+
+    for film in additions {
+        markdown = write_film_to_markdown(&markdown, film, scale);
+    }
+
+    MergeResult {
+        markdown,
+        conflicts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::RatingLevel;
+
+    const HISTORY: &str = "# Watch history
+
+Notes on what we watched, with [links](https://example.com) that should survive edits.
+
+## 2024
+
+### January
+
+- 3rd: Heat - very good #cinema @alex
+- Alien (rewatch) - goat
+
+  Still holds up.
+
+* Paddington 2 - goat
+
+### March
+
+1. Dune - good
+
+## 2023
+
+### December
+
+- Klaus - meh
+";
+
+    fn rating(label: &str) -> Rating {
+        RatingScale::default()
+            .parse(label)
+            .ok()
+            .expect("rating on the default scale")
+    }
+
+    fn film(title: &str, rating_label: &str, year: i16, month: i8) -> WatchedFilm {
+        WatchedFilm {
+            title: title.to_string(),
+            rating: rating(rating_label),
+            year_watched: year,
+            month_of_year_watched: MonthOfYear::try_from(month).expect("valid month"),
+            day_of_month_watched: None,
+            rewatch: false,
+            tags: vec![],
+            companions: vec![],
+            notes: None,
+        }
+    }
+
+    fn parse(markdown: &str) -> Vec<WatchedFilm> {
+        let (films, report) = parse_films_from_markdown(markdown, &RatingScale::default());
+        assert_eq!(report, ParseReport::default());
+        films
+    }
+
+    fn describe(films: &[WatchedFilm]) -> String {
+        films
+            .iter()
+            .map(|film| {
+                let mut line = format!(
+                    "{} {} {:?}: {} - {}",
+                    film.year_watched,
+                    film.month_of_year_watched,
+                    film.day_of_month_watched,
+                    film.title,
+                    film.rating,
+                );
+                if film.rewatch {
+                    line.push_str(" rewatch");
+                }
+                if !film.tags.is_empty() {
+                    line.push_str(&format!(" tags={:?}", film.tags));
+                }
+                if !film.companions.is_empty() {
+                    line.push_str(&format!(" companions={:?}", film.companions));
+                }
+                if let Some(notes) = &film.notes {
+                    line.push_str(&format!(" notes={notes:?}"));
+                }
+                line + "\n"
+            })
+            .collect()
+    }
+
+    /// The lines that differ between the two files, found by dropping the lines they share at the
+    /// start and end, so everything outside them is byte-identical.
+    fn changed_lines(before: &str, after: &str) -> String {
+        let before: Vec<&str> = before.split_inclusive('\n').collect();
+        let after: Vec<&str> = after.split_inclusive('\n').collect();
+
+        let prefix = before
+            .iter()
+            .zip(&after)
+            .take_while(|(a, b)| a == b)
+            .count();
+        let suffix = before[prefix..]
+            .iter()
+            .rev()
+            .zip(after[prefix..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        let removed = before[prefix..before.len() - suffix]
+            .iter()
+            .map(|line| format!("-|{line}"));
+        let inserted = after[prefix..after.len() - suffix]
+            .iter()
+            .map(|line| format!("+|{line}"));
+
+        removed.chain(inserted).collect()
+    }
+
+    fn new_films() -> Vec<WatchedFilm> {
+        vec![
+            film("Perfect Days", "very good", 2024, 1),
+            WatchedFilm {
+                day_of_month_watched: Some(2),
+                ..film("Godzilla Minus One", "good", 2024, 1)
+            },
+            film("Poor Things", "good", 2024, 2),
+            film("Past Lives", "goat", 2025, 6),
+            WatchedFilm {
+                rewatch: true,
+                tags: vec!["home".to_string()],
+                companions: vec!["sam".to_string()],
+                notes: Some("Better the second time.\n\n- The score\n- The ending".to_string()),
+                ..film("Anatomy of a Fall", "very good", 2022, 11)
+            },
+        ]
+    }
+
+    #[test]
+    fn parses_every_entry() {
+        insta::assert_snapshot!(describe(&parse(HISTORY)), @r#"
+        2024 January Some(3): Heat - very good tags=["cinema"] companions=["alex"]
+        2024 January None: Alien - goat rewatch notes="Still holds up."
+        2024 January None: Paddington 2 - goat
+        2024 March None: Dune - good
+        2023 December None: Klaus - meh
+        "#);
+    }
+
+    #[test]
+    fn written_entries_parse_back_alongside_the_existing_ones() {
+        let scale = RatingScale::default();
+        let existing = parse(HISTORY);
+
+        for film in new_films() {
+            let written = write_film_to_markdown(HISTORY, film.clone(), &scale);
+            let films = parse(&written);
+
+            assert_eq!(films.len(), existing.len() + 1, "{written}");
+            assert!(films.contains(&film), "{written}");
+            assert!(existing.iter().all(|x| films.contains(x)), "{written}");
+
+            // Writing the parsed file back out changes nothing.
+            for parsed in &films {
+                assert_eq!(
+                    edit_film_in_markdown(&written, parsed, parsed, &scale).as_deref(),
+                    Some(written.as_str())
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn titles_with_dashes_parse_back_as_written() {
+        let scale = RatingScale::default();
+        let films = vec![
+            WatchedFilm {
+                tags: vec!["cinema".to_string()],
+                ..film("Spider-Man", "very good", 2024, 1)
+            },
+            WatchedFilm {
+                day_of_month_watched: Some(4),
+                rewatch: true,
+                ..film("X-Men: First Class", "good", 2024, 1)
+            },
+            film("Mission: Impossible - Dead Reckoning", "meh", 2024, 1),
+        ];
+
+        let written = films.iter().fold(String::new(), |markdown, film| {
+            write_film_to_markdown(markdown, film.clone(), &scale)
+        });
+        let parsed = parse(&written);
+
+        assert!(films.iter().all(|film| parsed.contains(film)), "{written}");
+        assert_eq!(parsed.len(), films.len(), "{written}");
+    }
+
+    #[test]
+    fn deleting_a_written_entry_restores_the_file() {
+        let scale = RatingScale::default();
+
+        for film in new_films() {
+            let written = write_film_to_markdown(HISTORY, film.clone(), &scale);
+
+            assert_eq!(
+                delete_film_from_markdown(&written, &film, &scale).as_deref(),
+                Some(HISTORY),
+                "{written}"
+            );
+        }
+    }
+
+    #[test]
+    fn rewriting_an_entry_unchanged_leaves_the_file_byte_identical() {
+        let scale = RatingScale::default();
+
+        for film in parse(HISTORY) {
+            assert_eq!(
+                edit_film_in_markdown(HISTORY, &film, &film, &scale).as_deref(),
+                Some(HISTORY)
+            );
+        }
+    }
+
+    #[test]
+    fn deleting_an_entry_leaves_the_others() {
+        let scale = RatingScale::default();
+        let existing = parse(HISTORY);
+
+        for film in &existing {
+            let deleted = delete_film_from_markdown(HISTORY, film, &scale).expect("entry found");
+            let remaining: Vec<_> = existing.iter().filter(|x| *x != film).cloned().collect();
+
+            assert_eq!(parse(&deleted), remaining, "{deleted}");
+        }
+    }
+
+    #[test]
+    fn writes_into_an_existing_month() {
+        let written = write_film_to_markdown(
+            HISTORY,
+            film("Perfect Days", "very good", 2024, 1),
+            &RatingScale::default(),
+        );
+
+        insta::assert_snapshot!(changed_lines(HISTORY, &written), @r"
+        +|* Perfect Days - very good
+        ");
+    }
+
+    #[test]
+    fn writes_after_the_last_entry_not_the_blank_line_after_the_list() {
+        let markdown = "## 2024\n\n### January\n\n- Heat - good\n\n### March\n\n- Dune - good\n";
+        let written = write_film_to_markdown(
+            markdown,
+            film("Perfect Days", "very good", 2024, 1),
+            &RatingScale::default(),
+        );
+
+        insta::assert_snapshot!(written, @r"
+        ## 2024
+
+        ### January
+
+        - Heat - good
+        - Perfect Days - very good
+
+        ### March
+
+        - Dune - good
+        ");
+    }
+
+    #[test]
+    fn writes_a_dated_entry_before_later_days() {
+        let film = WatchedFilm {
+            day_of_month_watched: Some(2),
+            ..film("Godzilla Minus One", "good", 2024, 1)
+        };
+        let markdown = "## 2024\n\n### January\n\n- 1st: Wonka - meh\n- 3rd: Heat - good\n";
+        let written = write_film_to_markdown(markdown, film, &RatingScale::default());
+
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        +|- 2nd: Godzilla Minus One - good
+        ");
+    }
+
+    #[test]
+    fn writes_a_new_month_in_order() {
+        let written = write_film_to_markdown(
+            HISTORY,
+            film("Poor Things", "good", 2024, 2),
+            &RatingScale::default(),
+        );
+
+        insta::assert_snapshot!(changed_lines(HISTORY, &written), @r"
+        +|### February
+        +|
+        +|- Poor Things - good
+        +|
+        ");
+    }
+
+    #[test]
+    fn writes_a_new_year_in_order() {
+        let written = write_film_to_markdown(
+            HISTORY,
+            film("Past Lives", "goat", 2025, 6),
+            &RatingScale::default(),
+        );
+
+        insta::assert_snapshot!(changed_lines(HISTORY, &written), @r"
+        +|## 2025
+        +|
+        +|### June
+        +|
+        +|- Past Lives - goat
+        +|
+        ");
+    }
+
+    #[test]
+    fn writes_notes_nested_under_the_entry() {
+        let film = WatchedFilm {
+            notes: Some("Better the second time.\n\n- The score".to_string()),
+            ..film("Klaus", "good", 2023, 12)
+        };
+        let written = write_film_to_markdown(HISTORY, film, &RatingScale::default());
+
+        insta::assert_snapshot!(changed_lines(HISTORY, &written), @r"
+        +|- Klaus - good
+        +|
+        +|  Better the second time.
+        +|
+        +|  - The score
+        ");
+    }
+
+    #[test]
+    fn edits_only_the_entry_and_its_notes() {
+        let scale = RatingScale::default();
+        let alien = parse(HISTORY)
+            .into_iter()
+            .find(|film| film.title == "Alien")
+            .expect("Alien is in the history");
+        let edited = WatchedFilm {
+            rating: rating("very good"),
+            notes: None,
+            ..alien.clone()
+        };
+        let written = edit_film_in_markdown(HISTORY, &alien, &edited, &scale).expect("edited");
+
+        insta::assert_snapshot!(changed_lines(HISTORY, &written), @r"
+        -|- Alien (rewatch) - goat
+        -|
+        -|  Still holds up.
+        +|- Alien (rewatch) - very good
+        ");
+    }
+
+    #[test]
+    fn entries_under_an_invalid_year_are_reported_not_filed_under_the_previous_year() {
+        let scale = RatingScale::default();
+        let markdown = "## 2024\n\n### May\n\n- A - good\n\n## 2O25\n\n### May\n\n- B - good\n";
+        let (films, report) = parse_films_from_markdown(markdown, &scale);
+
+        insta::assert_snapshot!(describe(&films), @"2024 May None: A - good");
+        let warnings: Vec<_> = report
+            .warnings
+            .iter()
+            .map(|warning| (warning.line, &warning.reason))
+            .collect();
+        insta::assert_debug_snapshot!(warnings, @r#"
+        [
+            (
+                7,
+                InvalidYear(
+                    "2O25",
+                ),
+            ),
+        ]
+        "#);
+
+        let written = write_film_to_markdown(markdown, film("C", "good", 2024, 5), &scale);
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        +|- C - good
+        ");
+    }
+
+    #[test]
+    fn entries_under_an_invalid_month_are_reported_not_filed_under_the_previous_month() {
+        let scale = RatingScale::default();
+        let markdown = "## 2024\n\n### May\n\n- A - good\n\n### Mya\n\n- B - good\n";
+        let (films, report) = parse_films_from_markdown(markdown, &scale);
+
+        insta::assert_snapshot!(describe(&films), @"2024 May None: A - good");
+        let warnings: Vec<_> = report
+            .warnings
+            .iter()
+            .map(|warning| (warning.line, &warning.reason))
+            .collect();
+        insta::assert_debug_snapshot!(warnings, @r#"
+        [
+            (
+                7,
+                InvalidMonth(
+                    "Mya",
+                ),
+            ),
+        ]
+        "#);
+
+        let written = write_film_to_markdown(markdown, film("C", "good", 2024, 5), &scale);
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        +|- C - good
+        ");
+        assert!(written.starts_with("## 2024\n\n### May\n\n- A - good\n- C - good\n"));
+    }
+
+    #[test]
+    fn entries_with_a_day_the_month_does_not_have_can_be_edited() {
+        let scale = RatingScale::default();
+        let markdown = "## 2024\n\n### April\n\n- 31st: A - good\n- B - good\n";
+        let (films, _) = parse_films_from_markdown(markdown, &scale);
+        let a = films[0].clone();
+        assert_eq!(a.day_of_month_watched, None);
+
+        let edited = WatchedFilm {
+            rating: rating("meh"),
+            ..a.clone()
+        };
+        let written = edit_film_in_markdown(markdown, &a, &edited, &scale).expect("edited");
+
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        -|- 31st: A - good
+        +|- A - meh
+        ");
+        assert_eq!(
+            delete_film_from_markdown(markdown, &a, &scale).as_deref(),
+            Some("## 2024\n\n### April\n\n- B - good\n")
+        );
+    }
+
+    #[test]
+    fn a_custom_scale_is_read_and_written_with_its_own_labels() {
+        let level = |label: &str, aliases: &[&str], value| RatingLevel {
+            label: label.to_string(),
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+            value,
+        };
+        let scale = RatingScale {
+            levels: vec![
+                level("★", &["1/3"], 2),
+                level("★½", &[], 3),
+                level("★★", &["2/3"], 4),
+                level("★★★", &["3/3"], 6),
+            ],
+        };
+        assert_eq!(scale.validate(), Ok(()));
+
+        let markdown = "## 2024\n\n### May\n\n- A - 3/3\n- B - ★½ #home\n";
+        let (films, report) = parse_films_from_markdown(markdown, &scale);
+        assert_eq!(report, ParseReport::default());
+        insta::assert_snapshot!(describe(&films), @r#"
+        2024 May None: A - ★★★
+        2024 May None: B - ★½ tags=["home"]
+        "#);
+
+        // The rating keeps the label it was read with until the level is relabelled.
+        let relabelled = RatingScale {
+            levels: vec![level("three stars", &[], 6)],
+        };
+        assert_eq!(relabelled.label(&films[0].rating), "three stars");
+        assert_eq!(scale.label(&films[1].rating), "★½");
+
+        // An entry written with an alias is found by its value and written with the label.
+        let edited = WatchedFilm {
+            rating: scale.parse("2/3").ok().expect("rating on the scale"),
+            ..films[0].clone()
+        };
+        let written = edit_film_in_markdown(markdown, &films[0], &edited, &scale).expect("edited");
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        -|- A - 3/3
+        +|- A - ★★
+        ");
+
+        let c = WatchedFilm {
+            rating: Rating {
+                label: "one and a half".to_string(),
+                value: 3,
+            },
+            ..film("C", "good", 2024, 5)
+        };
+        let written = write_film_to_markdown(markdown, c, &scale);
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        +|- C - ★½
+        ");
+    }
+
+    #[test]
+    fn editing_an_entry_keeps_the_blank_line_after_its_notes() {
+        let scale = RatingScale::default();
+        let markdown = "## 2024\n\n### May\n\n- A - good\n\n  Notes\n\n  - x\n\n- B - good\n";
+        let a = parse(markdown).remove(0);
+        let edited = WatchedFilm {
+            rating: rating("meh"),
+            ..a.clone()
+        };
+        let written = edit_film_in_markdown(markdown, &a, &edited, &scale).expect("edited");
+
+        insta::assert_snapshot!(changed_lines(markdown, &written), @r"
+        -|- A - good
+        +|- A - meh
+        ");
+    }
+
+    #[test]
+    fn deleting_the_last_entry_in_the_file_keeps_earlier_sections() {
+        let scale = RatingScale::default();
+        let markdown = "## 2024\n\n### January\n\n- A - good\n\n### February\n\n- B - good\n";
+        let deleted = delete_film_from_markdown(markdown, &film("B", "good", 2024, 2), &scale);
+
+        insta::assert_snapshot!(deleted.expect("entry found"), @r"
+        ## 2024
+
+        ### January
+
+        - A - good
+        ");
+    }
+
+    #[test]
+    fn deleting_the_only_entry_in_a_year_removes_its_headings() {
+        let scale = RatingScale::default();
+        let deleted = delete_film_from_markdown(HISTORY, &film("Klaus", "meh", 2023, 12), &scale)
+            .expect("entry found");
+
+        insta::assert_snapshot!(changed_lines(HISTORY, &deleted), @r"
+        -|
+        -|## 2023
+        -|
+        -|### December
+        -|
+        -|- Klaus - meh
+        ");
+    }
+
+    fn merge(base: &str, local: &str, remote: &str, prefer: Option<MergeSide>) -> MergeResult {
+        merge_markdown(base, local, remote, prefer, &RatingScale::default())
+    }
+
+    #[test]
+    fn merging_keeps_a_second_viewing_with_the_same_title() {
+        let scale = RatingScale::default();
+        let base = "## 2024\n\n### May\n\n- A - good\n";
+        let local = write_film_to_markdown(base, film("A", "good", 2024, 5), &scale);
+        let remote = "## 2024\n\n### May\n\n- A - good\n- B - good\n";
+        let merged = merge(base, &local, remote, None);
+
+        assert_eq!(merged.conflicts, vec![]);
+        insta::assert_snapshot!(changed_lines(remote, &merged.markdown), @r"
+        +|- A - good
+        ");
+    }
+
+    #[test]
+    fn merging_edits_the_same_viewing_of_a_film_seen_twice() {
+        let base = "## 2024\n\n### May\n\n- A - good\n- B - good\n- A - good\n";
+        let local = "## 2024\n\n### May\n\n- A - good\n- B - good\n- A - meh\n";
+        let remote = "## 2024\n\n### May\n\n- A - good\n- B - good\n- A - good\n- C - good\n";
+        let merged = merge(base, local, remote, None);
+
+        assert_eq!(merged.conflicts, vec![]);
+        insta::assert_snapshot!(changed_lines(remote, &merged.markdown), @r"
+        -|- A - good
+        +|- A - meh
+        ");
+    }
+
+    #[test]
+    fn merging_a_title_edit_keeps_the_entry_in_place() {
+        let base = "## 2024\n\n### May\n\n- A - good\n- B - good\n- C - good\n";
+        let local = "## 2024\n\n### May\n\n- A - good\n- Bee - good\n- C - good\n";
+        let remote = "## 2024\n\n### May\n\n- A - good\n- B - good\n- C - meh\n";
+        let merged = merge(base, local, remote, None);
+
+        assert_eq!(merged.conflicts, vec![]);
+        insta::assert_snapshot!(merged.markdown, @r"
+        ## 2024
+
+        ### May
+
+        - A - good
+        - Bee - good
+        - C - meh
+        ");
+    }
+
+    #[test]
+    fn merging_keeps_edits_made_to_different_entries_on_both_sides() {
+        let base = "## 2024\n\n### May\n\n- A - good\n- B - good\n- C - good\n";
+        let local = "## 2024\n\n### May\n\n- A - meh\n- C - good\n";
+        let remote = "## 2024\n\n### May\n\n- A - good\n- B - good\n- C (rewatch) - good\n\n\
+                      ### June\n\n- D - goat\n";
+        let merged = merge(base, local, remote, None);
+
+        assert_eq!(merged.conflicts, vec![]);
+        insta::assert_snapshot!(merged.markdown, @r"
+        ## 2024
+
+        ### May
+
+        - A - meh
+        - C (rewatch) - good
+
+        ### June
+
+        - D - goat
+        ");
+    }
+
+    #[test]
+    fn merging_reports_an_entry_changed_on_both_sides_unless_a_side_is_preferred() {
+        let base = "## 2024\n\n### May\n\n- A - good\n- B - good\n";
+        let local = "## 2024\n\n### May\n\n- A - good\n- B - meh\n";
+        let remote = "## 2024\n\n### May\n\n- A - good\n- B - goat\n";
+
+        let merged = merge(base, local, remote, None);
+        assert_eq!(merged.markdown, remote);
+        assert_eq!(
+            merged.conflicts,
+            vec![MergeConflict {
+                base: Some(film("B", "good", 2024, 5)),
+                local: Some(film("B", "meh", 2024, 5)),
+                remote: Some(film("B", "goat", 2024, 5)),
+            }]
+        );
+
+        let merged = merge(base, local, remote, Some(MergeSide::Local));
+        assert_eq!(
+            (merged.markdown.as_str(), merged.conflicts),
+            (local, vec![])
+        );
+
+        let merged = merge(base, local, remote, Some(MergeSide::Remote));
+        assert_eq!(
+            (merged.markdown.as_str(), merged.conflicts),
+            (remote, vec![])
+        );
+    }
 }
@@ -0,0 +1,78 @@
+use crate::error::AppError;
+use crate::film::FilmChange;
+use crate::markdown::{MergeConflict, MergeSide};
+use crate::store::StoredValue;
+use crate::{Effect, Event};
+use crux_core::command::RequestBuilder;
+use crux_http::http::convert::{Deserialize, Serialize};
+use std::future::Future;
+use uuid::Uuid;
+
+const CHANGE_QUEUE_STORAGE_KEY_PREFIX: &str = "change_queue";
+
+fn stored_queue(login: &str) -> StoredValue<Vec<QueuedChange>> {
+    StoredValue::new(format!("{CHANGE_QUEUE_STORAGE_KEY_PREFIX}/{login}"))
+}
+
+/// A film change that has been made locally but not yet committed to the watch history file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct QueuedChange {
+    pub id: String,
+    pub change: FilmChange,
+    /// The watch history the change was made against, with any earlier queued changes applied,
+    /// used as the common ancestor when merging with the file on GitHub.
+    pub base: Option<String>,
+    /// How conflicting entries should be settled, once the user has chosen.
+    pub resolution: Option<MergeSide>,
+    pub status: QueuedChangeStatus,
+}
+
+impl QueuedChange {
+    pub fn new(change: FilmChange, base: Option<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            change,
+            base,
+            resolution: None,
+            status: QueuedChangeStatus::Pending,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum QueuedChangeStatus {
+    Pending,
+    /// The change was rejected for a reason that retrying alone will not fix, so it is held back
+    /// until it is retried or discarded.
+    Failed(AppError),
+    /// The file on GitHub changed the same entries in a different way, so the user has to choose
+    /// which version to keep.
+    Conflicted(Vec<MergeConflict>),
+}
+
+#[derive(Clone)]
+pub struct ChangeQueueStore;
+
+impl ChangeQueueStore {
+    pub fn get_queue(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Vec<QueuedChange>>> {
+        stored_queue(login).get().map(Option::unwrap_or_default)
+    }
+
+    pub fn set_queue(
+        &self,
+        login: &str,
+        queue: Vec<QueuedChange>,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_queue(login).set(queue)
+    }
+
+    pub fn remove_queue(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_queue(login).delete()
+    }
+}
@@ -0,0 +1,225 @@
+use crate::clock::Clock;
+use crate::github::GitHubApiError;
+use crate::{Effect, Event};
+use chrono::{DateTime, Duration, Utc};
+use crux_core::command::CommandContext;
+use crux_http::http::convert::{Deserialize, Serialize};
+use crux_http::http::StatusCode;
+use crux_http::{HttpError, Response};
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+/// How long to wait after a secondary rate limit when GitHub gives no reset time, as recommended
+/// by the GitHub REST API documentation.
+const SECONDARY_RATE_LIMIT_BACKOFF: Duration = Duration::seconds(60);
+
+/// The request budget GitHub reported with the most recent response.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RateLimit {
+    pub limit: u32,
+    pub remaining: u32,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl RateLimit {
+    fn from_response<T>(response: &Response<T>) -> Option<Self> {
+        let header = |name: &str| {
+            response
+                .header(name)
+                .and_then(|values| values.last().as_str().parse::<i64>().ok())
+        };
+
+        Some(Self {
+            limit: header("x-ratelimit-limit")?.try_into().ok()?,
+            remaining: header("x-ratelimit-remaining")?.try_into().ok()?,
+            reset_at: DateTime::from_timestamp(header("x-ratelimit-reset")?, 0)?,
+        })
+    }
+}
+
+/// The body GitHub sends with an error response.
+#[derive(Deserialize)]
+struct GitHubErrorResponse {
+    documentation_url: Option<String>,
+}
+
+impl GitHubErrorResponse {
+    /// Whether the error points at GitHub's rate limit documentation, which is how a `403` for a
+    /// secondary rate limit differs from one for missing permissions without its headers.
+    fn is_rate_limit(body: &[u8]) -> bool {
+        serde_json::from_slice::<Self>(body)
+            .ok()
+            .and_then(|response| Url::parse(&response.documentation_url?).ok())
+            .is_some_and(|url| {
+                url.path_segments()
+                    .into_iter()
+                    .flatten()
+                    .chain(url.fragment())
+                    .any(|section| section.split('-').any(|word| word == "rate"))
+            })
+    }
+}
+
+/// Remembers the budget from the last response, so that a request rejected by a rate limit can
+/// be given the time the budget resets.
+#[derive(Clone)]
+pub struct RateLimitTracker {
+    clock: Arc<dyn Clock>,
+    last: Arc<Mutex<Option<RateLimit>>>,
+}
+
+impl RateLimitTracker {
+    pub fn new(clock: Arc<dyn Clock>) -> Self {
+        Self {
+            clock,
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Sends the budget from a successful response to the app, and turns a rate-limit rejection
+    /// into [`GitHubApiError::RateLimited`].
+    ///
+    /// crux_http hands back error responses as [`HttpError::Http`] without their headers, so
+    /// neither `retry-after` nor the `x-ratelimit-*` headers of the rejection can be read. GitHub
+    /// only rejects a request for its primary rate limit once the budget is used up, so that
+    /// reset time comes from the last successful response. A secondary rate limit falls back to
+    /// the one minute GitHub asks clients to wait when there is no `retry-after`.
+    pub fn track<T>(
+        &self,
+        ctx: &CommandContext<Effect, Event>,
+        result: Result<Response<T>, HttpError>,
+    ) -> Result<Response<T>, GitHubApiError> {
+        match result {
+            Ok(response) => {
+                if let Some(rate_limit) = RateLimit::from_response(&response) {
+                    *self.last.lock().expect("rate limit lock poisoned") = Some(rate_limit.clone());
+                    ctx.send_event(Event::RateLimitUpdated(rate_limit));
+                }
+
+                Ok(response)
+            }
+            Err(HttpError::Http {
+                code,
+                message,
+                body,
+            }) => match self.rejected_until(code, body.as_deref()) {
+                Some(reset_at) => Err(GitHubApiError::RateLimited { reset_at }),
+                None => Err(GitHubApiError::HttpError(HttpError::Http {
+                    code,
+                    message,
+                    body,
+                })),
+            },
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// When a request rejected with `code` can be sent again, or `None` if it wasn't rejected by
+    /// a rate limit.
+    fn rejected_until(&self, code: StatusCode, body: Option<&[u8]>) -> Option<DateTime<Utc>> {
+        let now = self.clock.now();
+
+        let exhausted_until = self
+            .last
+            .lock()
+            .expect("rate limit lock poisoned")
+            .as_ref()
+            .filter(|rate_limit| rate_limit.remaining == 0 && rate_limit.reset_at > now)
+            .map(|rate_limit| rate_limit.reset_at);
+
+        match code {
+            StatusCode::Forbidden | StatusCode::TooManyRequests if exhausted_until.is_some() => {
+                exhausted_until
+            }
+            StatusCode::TooManyRequests => Some(now + SECONDARY_RATE_LIMIT_BACKOFF),
+            StatusCode::Forbidden if body.is_some_and(GitHubErrorResponse::is_rate_limit) => {
+                Some(now + SECONDARY_RATE_LIMIT_BACKOFF)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::FixedClock;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2025-03-31T12:00:00Z")
+            .expect("valid date")
+            .with_timezone(&Utc)
+    }
+
+    fn tracker(last: Option<RateLimit>) -> RateLimitTracker {
+        let tracker = RateLimitTracker::new(Arc::new(FixedClock::new(now())));
+        *tracker.last.lock().unwrap() = last;
+        tracker
+    }
+
+    fn error_body(documentation_url: &str) -> Vec<u8> {
+        serde_json::json!({
+            "message": "Forbidden",
+            "documentation_url": documentation_url,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    #[test]
+    fn a_used_up_budget_waits_for_its_reset() {
+        let reset_at = now() + Duration::minutes(20);
+        let tracker = tracker(Some(RateLimit {
+            limit: 5000,
+            remaining: 0,
+            reset_at,
+        }));
+
+        assert_eq!(
+            tracker.rejected_until(StatusCode::Forbidden, None),
+            Some(reset_at)
+        );
+        assert_eq!(
+            tracker.rejected_until(StatusCode::TooManyRequests, None),
+            Some(reset_at)
+        );
+    }
+
+    #[test]
+    fn secondary_rate_limits_back_off_for_a_minute() {
+        let tracker = tracker(Some(RateLimit {
+            limit: 5000,
+            remaining: 4000,
+            reset_at: now() + Duration::minutes(20),
+        }));
+        let body = error_body(concat!(
+            "https://docs.github.com/rest/overview/rate-limits-for-the-rest-api",
+            "#about-secondary-rate-limits",
+        ));
+
+        assert_eq!(
+            tracker.rejected_until(StatusCode::Forbidden, Some(body.as_slice())),
+            Some(now() + SECONDARY_RATE_LIMIT_BACKOFF)
+        );
+        assert_eq!(
+            tracker.rejected_until(StatusCode::TooManyRequests, None),
+            Some(now() + SECONDARY_RATE_LIMIT_BACKOFF)
+        );
+    }
+
+    #[test]
+    fn other_rejections_are_not_rate_limits() {
+        let tracker = tracker(None);
+        let body = error_body("https://docs.github.com/rest/repos/contents#get-repository-content");
+
+        assert_eq!(
+            tracker.rejected_until(StatusCode::Forbidden, Some(body.as_slice())),
+            None
+        );
+        assert_eq!(
+            tracker.rejected_until(StatusCode::Forbidden, Some(b"<html>".as_slice())),
+            None
+        );
+        assert_eq!(tracker.rejected_until(StatusCode::NotFound, None), None);
+    }
+}
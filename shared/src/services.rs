@@ -1,15 +1,36 @@
+use crate::accounts::AccountStore;
+use crate::cache::WatchHistoryCacheStore;
+use crate::clock::{Clock, SystemClock};
 use crate::config::Configuration;
+use crate::etag::ETagStore;
 use crate::github::GitHubClient;
+use crate::login::PendingLoginStore;
+use crate::queue::ChangeQueueStore;
+use crate::settings::SettingsStore;
 use crate::tokens::TokenStore;
+use std::sync::Arc;
 
 pub struct Services {
     pub github_client: GitHubClient,
+    pub account_store: AccountStore,
     pub token_store: TokenStore,
+    pub etag_store: ETagStore,
+    pub settings_store: SettingsStore,
+    pub pending_login_store: PendingLoginStore,
+    pub watch_history_cache_store: WatchHistoryCacheStore,
+    pub change_queue_store: ChangeQueueStore,
     pub config: Configuration,
+    pub clock: Arc<dyn Clock>,
 }
 
 impl Default for Services {
     fn default() -> Self {
+        Self::with_clock(Arc::new(SystemClock))
+    }
+}
+
+impl Services {
+    pub fn with_clock(clock: Arc<dyn Clock>) -> Self {
         #[cfg(target_os = "android")]
         android_logger::init_once(
             android_logger::Config::default()
@@ -20,19 +41,29 @@ impl Default for Services {
         let config: Configuration =
             toml::from_str(include_str!("config.toml")).expect("failed parsing configuration");
 
+        let account_store = AccountStore;
         let token_store = TokenStore;
+        let etag_store = ETagStore;
         let github_client = GitHubClient::new(
+            account_store.clone(),
             token_store.clone(),
+            etag_store.clone(),
+            clock.clone(),
             "https://api.github.com",
-            config.github.client_id.clone(),
-            config.github.client_secret.clone(),
-            config.github.redirect_uri.clone(),
+            config.github.clone(),
         );
 
         Self {
             github_client,
+            account_store,
             token_store,
+            etag_store,
+            settings_store: SettingsStore,
+            pending_login_store: PendingLoginStore,
+            watch_history_cache_store: WatchHistoryCacheStore,
+            change_queue_store: ChangeQueueStore,
             config,
+            clock,
         }
     }
 }
\ No newline at end of file
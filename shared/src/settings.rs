@@ -0,0 +1,71 @@
+use crate::film::RatingScale;
+use crate::store::StoredValue;
+use crate::{Effect, Event};
+use crux_core::command::RequestBuilder;
+use crux_http::http::convert::{Deserialize, Serialize};
+use std::future::Future;
+
+const WATCH_HISTORY_SETTINGS_STORAGE_KEY_PREFIX: &str = "watch_history_settings";
+
+fn stored_settings(login: &str) -> StoredValue<WatchHistorySettings> {
+    StoredValue::new(format!(
+        "{WATCH_HISTORY_SETTINGS_STORAGE_KEY_PREFIX}/{login}"
+    ))
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct WatchHistorySettings {
+    /// The user or organisation owning the repository, or `None` for the signed-in user.
+    pub owner: Option<String>,
+    pub repo: String,
+    /// The branch, tag or commit to read from and commit to, or `None` for the default branch.
+    pub git_ref: Option<String>,
+    pub path: String,
+    /// The ratings the watch history file is read and written with.
+    pub rating_scale: RatingScale,
+}
+
+impl Default for WatchHistorySettings {
+    fn default() -> Self {
+        Self {
+            owner: None,
+            repo: "notes".to_string(),
+            git_ref: None,
+            path: "watch_history.md".to_string(),
+            rating_scale: RatingScale::default(),
+        }
+    }
+}
+
+impl WatchHistorySettings {
+    pub fn owner_or(&self, login: &str) -> String {
+        self.owner.clone().unwrap_or(login.to_string())
+    }
+}
+
+#[derive(Clone)]
+pub struct SettingsStore;
+
+impl SettingsStore {
+    pub fn get_settings(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<WatchHistorySettings>>> {
+        stored_settings(login).get()
+    }
+
+    pub fn set_settings(
+        &self,
+        login: &str,
+        settings: WatchHistorySettings,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_settings(login).set(settings)
+    }
+
+    pub fn remove_settings(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_settings(login).delete()
+    }
+}
@@ -0,0 +1,48 @@
+use crate::{Effect, Event};
+use crux_core::command::RequestBuilder;
+use crux_kv::KeyValue;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::future::Future;
+use std::marker::PhantomData;
+
+/// A value kept under a key in the shell's key-value store, encoded with bincode.
+pub struct StoredValue<T> {
+    key: String,
+    value: PhantomData<fn() -> T>,
+}
+
+impl<T> StoredValue<T>
+where
+    T: Serialize + DeserializeOwned + Send + 'static,
+{
+    pub fn new(key: impl Into<String>) -> Self {
+        Self {
+            key: key.into(),
+            value: PhantomData,
+        }
+    }
+
+    /// The stored value, or `None` if there is none or it can't be read.
+    pub fn get(&self) -> RequestBuilder<Effect, Event, impl Future<Output = Option<T>>> {
+        self.get_with(|data| bincode::deserialize::<T>(data).ok())
+    }
+
+    /// The stored value read with `decode`, for values that may have been stored in an earlier
+    /// format.
+    pub fn get_with(
+        &self,
+        decode: fn(&[u8]) -> Option<T>,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<T>>> {
+        KeyValue::get(self.key.clone())
+            .map(move |x| x.ok().flatten().and_then(|data| decode(&data)))
+    }
+
+    pub fn set(&self, value: T) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        KeyValue::set(self.key.clone(), bincode::serialize(&value).unwrap()).map(|_| ())
+    }
+
+    pub fn delete(&self) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        KeyValue::delete(self.key.clone()).map(|_| ())
+    }
+}
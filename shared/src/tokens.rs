@@ -1,40 +1,123 @@
+use crate::store::StoredValue;
 use crate::{Effect, Event};
 use chrono::{DateTime, Utc};
 use crux_core::command::RequestBuilder;
 use crux_http::http::convert::{Deserialize, Serialize};
-use crux_kv::KeyValue;
+use std::fmt::{Debug, Formatter};
 use std::future::Future;
 
-const GITHUB_TOKENS_STORAGE_KEY: &str = "github_tokens";
+const GITHUB_TOKENS_STORAGE_KEY_PREFIX: &str = "github_tokens";
+
+fn stored_tokens(login: &str) -> StoredValue<Tokens> {
+    StoredValue::new(format!("{GITHUB_TOKENS_STORAGE_KEY_PREFIX}/{login}"))
+}
+
+/// Tokens were kept under the bare prefix before accounts were keyed by login.
+const LEGACY_GITHUB_TOKENS_STORAGE_KEY: &str = GITHUB_TOKENS_STORAGE_KEY_PREFIX;
+
+/// The shape tokens were stored in under [`LEGACY_GITHUB_TOKENS_STORAGE_KEY`], before personal
+/// access tokens made expiry and refreshing optional.
+#[derive(Deserialize)]
+struct LegacyTokens {
+    access_token: LegacyToken,
+    refresh_token: LegacyToken,
+}
+
+#[derive(Deserialize)]
+struct LegacyToken {
+    token_type: String,
+    access_token: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl From<LegacyToken> for Token {
+    fn from(value: LegacyToken) -> Self {
+        Token::new(value.token_type, value.access_token, Some(value.expires_at))
+    }
+}
+
+fn decode_legacy_tokens(data: &[u8]) -> Option<Tokens> {
+    bincode::deserialize::<Tokens>(data).ok().or_else(|| {
+        bincode::deserialize::<LegacyTokens>(data)
+            .ok()
+            .map(Tokens::from)
+    })
+}
+
+impl From<LegacyTokens> for Tokens {
+    fn from(value: LegacyTokens) -> Self {
+        Self {
+            access_token: value.access_token.into(),
+            refresh_token: Some(value.refresh_token.into()),
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Tokens {
     pub access_token: Token,
-    pub refresh_token: Token,
+    /// `None` for tokens that cannot be refreshed, such as personal access tokens.
+    pub refresh_token: Option<Token>,
+}
+
+impl Tokens {
+    pub fn personal_access_token(access_token: impl Into<String>) -> Self {
+        Self {
+            access_token: Token::new("Bearer".to_string(), access_token.into(), None),
+            refresh_token: None,
+        }
+    }
+}
+
+/// A credential that is serialised as the plain string but left out of `Debug` output, so
+/// logging an event or a token never writes the secret to the device log.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<redacted>")
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
 pub struct Token {
     pub token_type: String,
-    pub access_token: String,
-    pub expires_at: DateTime<Utc>,
+    pub access_token: Secret,
+    /// `None` for tokens that never expire.
+    pub expires_at: Option<DateTime<Utc>>,
 }
 
 impl Token {
-    pub fn new(token_type: String, access_token: String, expires_at: DateTime<Utc>) -> Self {
+    pub fn new(
+        token_type: String,
+        access_token: String,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Self {
         Self {
             token_type,
-            access_token,
+            access_token: Secret::new(access_token),
             expires_at,
         }
     }
 
-    pub fn is_valid(&self) -> bool {
-        Utc::now() < self.expires_at
+    pub fn is_valid_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.map_or(true, |expires_at| now < expires_at)
     }
 
     pub fn to_authorization_header_value(&self) -> String {
-        format!("{} {}", self.token_type, self.access_token)
+        format!("{} {}", self.token_type, self.access_token.expose())
     }
 }
 
@@ -44,26 +127,107 @@ pub struct TokenStore;
 impl TokenStore {
     pub fn get_tokens(
         &self,
+        login: &str,
     ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<Tokens>>> {
-        KeyValue::get(GITHUB_TOKENS_STORAGE_KEY).map(|x| {
-            x.ok()
-                .flatten()
-                .and_then(|data| bincode::deserialize::<Tokens>(&data).ok())
-        })
+        stored_tokens(login).get()
     }
 
     pub fn set_tokens(
         &self,
+        login: &str,
         tokens: Tokens,
     ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
-        KeyValue::set(
-            GITHUB_TOKENS_STORAGE_KEY,
-            bincode::serialize(&tokens).unwrap(),
-        )
-        .map(|_| ())
+        stored_tokens(login).set(tokens)
+    }
+
+    pub fn remove_tokens(
+        &self,
+        login: &str,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        stored_tokens(login).delete()
     }
 
-    pub fn remove_tokens(&self) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
-        KeyValue::delete(GITHUB_TOKENS_STORAGE_KEY).map(|_| ())
+    /// The tokens of whoever was signed in before accounts were keyed by login, so they can be
+    /// moved to that account instead of signing the user out.
+    pub fn get_legacy_tokens(
+        &self,
+    ) -> RequestBuilder<Effect, Event, impl Future<Output = Option<Tokens>>> {
+        StoredValue::new(LEGACY_GITHUB_TOKENS_STORAGE_KEY).get_with(decode_legacy_tokens)
+    }
+
+    pub fn remove_legacy_tokens(&self) -> RequestBuilder<Effect, Event, impl Future<Output = ()>> {
+        StoredValue::<Tokens>::new(LEGACY_GITHUB_TOKENS_STORAGE_KEY).delete()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debug_output_leaves_out_the_token() {
+        let login = Event::LoginWithToken(Secret::new("ghp_secret"));
+        let tokens = Tokens::personal_access_token("ghp_secret");
+
+        assert!(!format!("{login:?}").contains("ghp_secret"));
+        assert!(!format!("{tokens:?}").contains("ghp_secret"));
+    }
+
+    #[test]
+    fn tokens_stored_before_redaction_still_load() {
+        #[derive(Serialize)]
+        struct StoredToken {
+            token_type: String,
+            access_token: String,
+            expires_at: Option<DateTime<Utc>>,
+        }
+
+        let stored = bincode::serialize(&StoredToken {
+            token_type: "Bearer".to_string(),
+            access_token: "ghp_secret".to_string(),
+            expires_at: None,
+        })
+        .unwrap();
+
+        assert_eq!(
+            bincode::deserialize::<Token>(&stored).unwrap(),
+            Token::new("Bearer".to_string(), "ghp_secret".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn legacy_tokens_move_over_with_their_expiry() {
+        #[derive(Serialize)]
+        struct StoredToken<'a> {
+            token_type: &'a str,
+            access_token: &'a str,
+            expires_at: DateTime<Utc>,
+        }
+
+        let expires_at = DateTime::parse_from_rfc3339("2025-01-01T09:00:00Z")
+            .expect("valid date")
+            .with_timezone(&Utc);
+        let token = |access_token| StoredToken {
+            token_type: "bearer",
+            access_token,
+            expires_at,
+        };
+        let stored = bincode::serialize(&(token("ghu_access"), token("ghr_refresh"))).unwrap();
+
+        assert_eq!(
+            decode_legacy_tokens(&stored),
+            Some(Tokens {
+                access_token: Token::new(
+                    "bearer".to_string(),
+                    "ghu_access".to_string(),
+                    Some(expires_at)
+                ),
+                refresh_token: Some(Token::new(
+                    "bearer".to_string(),
+                    "ghr_refresh".to_string(),
+                    Some(expires_at)
+                )),
+            })
+        );
     }
 }